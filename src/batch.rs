@@ -0,0 +1,431 @@
+use std::{convert::Infallible, fmt, io};
+
+use crate::{
+    checker::{SafetyChecker, Violation},
+    coin::CoinType,
+    outcome::Outcome,
+    process::{Id, ProcessConfig},
+    run::Run,
+    step::{Algorithm, Behavior},
+    termination::{Summary, Termination},
+    transport::Transport,
+};
+
+/// Many runs of the same setup with different seeds, to measure how many
+/// phases the processes need to decide.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Batch {
+    pub num_processes: usize,
+    pub num_zeros: usize,
+    pub num_adversaries: usize,
+    pub behavior: Behavior,
+    pub coin: CoinType,
+    pub runs: usize,
+    /// A run that hasn't terminated after this phase counts as failed.
+    pub max_phase: u64,
+    /// Run `i` uses seed `seed + i`.
+    pub seed: u64,
+}
+
+impl Batch {
+    /// Runs `simulate`, flipping the batch's coin, on fresh transports for
+    /// every run.
+    ///
+    /// Every correct process must be able to reach `max_phase`, so with the
+    /// threaded transports there should be fewer adversaries than would block
+    /// the correct processes forever.
+    pub fn run(&self, transports: impl Fn(usize) -> Vec<Box<dyn Transport>>) -> BatchReport {
+        let report: Result<_, Infallible> = self.run_with(|seed, termination| {
+            let processes = ProcessConfig::for_simulation(
+                self.num_processes,
+                self.num_zeros,
+                self.num_adversaries,
+                &self.behavior,
+            );
+            let algorithm = Algorithm {
+                coin: self.coin,
+                ..Algorithm::default()
+            };
+            let mut run = Run::spawn(
+                processes,
+                self.num_adversaries,
+                algorithm,
+                transports(self.num_processes),
+                seed,
+                termination,
+                None,
+            );
+            let violations = self.check(&mut run);
+            Ok((run.summary(), violations))
+        });
+        let Ok(report) = report;
+        report
+    }
+
+    /// Like `run`, but leaves running a single seed under a termination
+    /// policy to `run`, e.g. to use the discrete-event simulator. `check`
+    /// helps it find the safety violations. The batch stops at the first run
+    /// that fails to start.
+    pub fn run_with<E>(
+        &self,
+        mut run: impl FnMut(u64, Termination) -> Result<(Summary, Vec<Violation>), E>,
+    ) -> Result<BatchReport, E> {
+        let termination = Termination {
+            max_phase: Some(self.max_phase),
+            all_correct_decided: true,
+            ..Termination::default()
+        };
+        let mut report = BatchReport {
+            runs: 0,
+            unterminated: 0,
+            unsafe_runs: 0,
+            decision_phases: vec![vec![]; self.num_processes],
+        };
+        for i in 0..self.runs {
+            let (summary, violations) = run(self.seed.wrapping_add(i as u64), termination.clone())?;
+            report.add(&summary, &violations);
+        }
+        Ok(report)
+    }
+
+    /// Consumes the outcomes of a run, returning the safety violations.
+    pub fn check(&self, outcomes: impl Iterator<Item = (Id, Outcome)>) -> Vec<Violation> {
+        let mut checker = SafetyChecker::for_simulation(
+            self.num_processes,
+            self.num_zeros,
+            self.num_adversaries,
+            &self.behavior,
+        );
+        outcomes
+            .filter_map(|(id, outcome)| checker.observe(&id, &outcome).err())
+            .collect()
+    }
+}
+
+/// The decision phases of every process over the runs of a batch.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchReport {
+    pub runs: usize,
+    /// The runs in which some correct process didn't decide.
+    pub unterminated: usize,
+    /// The runs in which agreement or validity was violated.
+    pub unsafe_runs: usize,
+    /// `decision_phases[i]` has the phase process `i` decided in, for every
+    /// run in which it did.
+    pub decision_phases: Vec<Vec<u64>>,
+}
+
+impl BatchReport {
+    fn add(&mut self, summary: &Summary, violations: &[Violation]) {
+        self.runs += 1;
+        if !summary.all_correct_decided {
+            self.unterminated += 1;
+        }
+        if !violations.is_empty() {
+            self.unsafe_runs += 1;
+        }
+        for (phases, decision) in self.decision_phases.iter_mut().zip(&summary.decisions) {
+            if let Some((phase, _)) = decision {
+                phases.push(phase.0);
+            }
+        }
+    }
+
+    /// The fraction of runs that didn't terminate within the bound.
+    pub fn unterminated_fraction(&self) -> f64 {
+        if self.runs == 0 {
+            return 0.0;
+        }
+        self.unterminated as f64 / self.runs as f64
+    }
+
+    /// The decision phases of process `id`, or `None` if it never decided.
+    pub fn stats(&self, id: usize) -> Option<Stats> {
+        Stats::of(&self.decision_phases[id])
+    }
+
+    /// The decision phases of all the processes together.
+    pub fn overall(&self) -> Option<Stats> {
+        Stats::of(&self.decision_phases.concat())
+    }
+
+    /// Writes one row per process, and one for all of them, with the columns
+    /// `process,runs,unterminated,unterminated_fraction,decided,mean,median,p99,max`
+    /// as in a sweep table.
+    pub fn write_csv(&self, mut writer: impl io::Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "process,runs,unterminated,unterminated_fraction,decided,mean,median,p99,max"
+        )?;
+        let rows = (0..self.decision_phases.len())
+            .map(|id| (id.to_string(), self.stats(id)))
+            .chain(std::iter::once((String::from("all"), self.overall())));
+        for (process, stats) in rows {
+            match stats {
+                Some(stats) => writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{},{},{}",
+                    process,
+                    self.runs,
+                    self.unterminated,
+                    self.unterminated_fraction(),
+                    stats.count,
+                    stats.mean,
+                    stats.median,
+                    stats.p99,
+                    stats.max
+                )?,
+                None => writeln!(
+                    writer,
+                    "{},{},{},{},0,,,,",
+                    process,
+                    self.runs,
+                    self.unterminated,
+                    self.unterminated_fraction()
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for BatchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Runs: {}, failed to terminate: {} ({:.2}%), unsafe: {}",
+            self.runs,
+            self.unterminated,
+            100.0 * self.unterminated_fraction(),
+            self.unsafe_runs
+        )?;
+        let rows = (0..self.decision_phases.len())
+            .map(|id| (format!("Process {}", id), self.stats(id)))
+            .chain(std::iter::once((String::from("All"), self.overall())));
+        for (process, stats) in rows {
+            match stats {
+                Some(stats) => writeln!(f, "{}: {}", process, stats)?,
+                None => writeln!(f, "{}: never decided", process)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The distribution of the phases in which a process decided.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    pub count: usize,
+    pub mean: f64,
+    pub median: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+impl Stats {
+    fn of(phases: &[u64]) -> Option<Self> {
+        if phases.is_empty() {
+            return None;
+        }
+        let mut sorted = phases.to_vec();
+        sorted.sort_unstable();
+        // nearest rank
+        let percentile = |p: f64| sorted[((p * sorted.len() as f64).ceil() as usize).max(1) - 1];
+        Some(Stats {
+            count: sorted.len(),
+            mean: sorted.iter().sum::<u64>() as f64 / sorted.len() as f64,
+            median: percentile(0.5),
+            p99: percentile(0.99),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "decided {} times, mean {:.2}, median {}, p99 {}, max {}",
+            self.count, self.mean, self.median, self.p99, self.max
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        message::{Phase, Value},
+        scenario::{Scenario, TransportType},
+        simulator::{RandomScheduler, Simulator},
+        transport::MessageChannel,
+    };
+
+    #[test]
+    fn stats() {
+        let phases = (1..=200).collect::<Vec<_>>();
+        assert_eq!(
+            Stats::of(&phases),
+            Some(Stats {
+                count: 200,
+                mean: 100.5,
+                median: 100,
+                p99: 198,
+                max: 200,
+            })
+        );
+        assert_eq!(Stats::of(&[]), None);
+    }
+
+    #[test]
+    fn csv() {
+        let mut report = BatchReport {
+            runs: 0,
+            unterminated: 0,
+            unsafe_runs: 0,
+            decision_phases: vec![vec![]; 2],
+        };
+        report.add(
+            &Summary {
+                decisions: vec![Some((Phase(2), Value::One)), None],
+                last_phases: vec![Some(Phase(3)), Some(Phase(3))],
+                failures: vec![None, None],
+                stalls: vec![None, None],
+                all_correct_decided: false,
+            },
+            &[],
+        );
+        let mut csv = vec![];
+        report.write_csv(&mut csv).expect("write csv");
+        assert_eq!(
+            String::from_utf8(csv).expect("utf-8"),
+            "process,runs,unterminated,unterminated_fraction,decided,mean,median,p99,max\n\
+             0,1,1,1,1,2,2,2,2\n\
+             1,1,1,1,0,,,,\n\
+             all,1,1,1,1,2,2,2,2\n"
+        );
+        assert_eq!(report.unterminated_fraction(), 1.0);
+    }
+
+    #[test]
+    fn correct_processes_always_decide() {
+        let batch = Batch {
+            num_processes: 4,
+            num_zeros: 2,
+            num_adversaries: 1,
+            behavior: Behavior::Crashes,
+            coin: CoinType::Local,
+            runs: 5,
+            max_phase: 50,
+            seed: 0,
+        };
+        let report = batch.run(MessageChannel::new);
+        assert_eq!(report.runs, 5);
+        assert_eq!(report.unterminated, 0);
+        assert_eq!(report.unsafe_runs, 0);
+        assert!(report.decision_phases[1..].iter().all(|p| p.len() == 5));
+    }
+
+    #[test]
+    fn discrete_batches_are_reproducible() {
+        let batch = Batch {
+            num_processes: 5,
+            num_zeros: 2,
+            num_adversaries: 2,
+            behavior: Behavior::RandomlyAdversarial,
+            coin: CoinType::Local,
+            runs: 3,
+            max_phase: 20,
+            seed: 7,
+        };
+        let run = || {
+            batch
+                .run_with(|seed, termination| {
+                    let mut simulator = Simulator::new(
+                        batch.num_processes,
+                        batch.num_zeros,
+                        batch.num_adversaries,
+                        batch.behavior.clone(),
+                        Box::new(RandomScheduler::new(seed, 10)),
+                        seed,
+                        termination,
+                    );
+                    let violations = batch.check(&mut simulator);
+                    Ok::<_, Infallible>((simulator.summary(), violations))
+                })
+                .expect("run batch")
+        };
+        assert_eq!(run(), run());
+    }
+
+    // the algorithm only tolerates crashes, so telling different processes
+    // different values can make them decide differently
+    #[test]
+    fn equivocation_breaks_agreement() {
+        let batch = Batch {
+            num_processes: 4,
+            num_zeros: 2,
+            num_adversaries: 1,
+            behavior: Behavior::Equivocates,
+            coin: CoinType::Local,
+            runs: 20,
+            max_phase: 100,
+            seed: 0,
+        };
+        let report = batch
+            .run_with(|seed, termination| {
+                let mut simulator = Simulator::new(
+                    batch.num_processes,
+                    batch.num_zeros,
+                    batch.num_adversaries,
+                    batch.behavior.clone(),
+                    Box::new(RandomScheduler::new(seed, 10)),
+                    seed,
+                    termination,
+                );
+                let violations = batch.check(&mut simulator);
+                Ok::<_, Infallible>((simulator.summary(), violations))
+            })
+            .expect("run batch");
+        assert!(report.unsafe_runs > 0);
+    }
+
+    #[test]
+    fn a_common_coin_decides_sooner() {
+        let report = |coin| {
+            let batch = Batch {
+                num_processes: 6,
+                num_zeros: 3,
+                num_adversaries: 1,
+                behavior: Behavior::Crashes,
+                coin,
+                runs: 20,
+                max_phase: 50,
+                seed: 0,
+            };
+            batch
+                .run_with(|seed, termination| {
+                    let mut scenario = Scenario::for_simulation(
+                        batch.num_processes,
+                        batch.num_zeros,
+                        batch.num_adversaries,
+                        &batch.behavior,
+                        TransportType::DiscreteRandom,
+                    );
+                    scenario.coin = batch.coin;
+                    scenario.seed = Some(seed);
+                    scenario.termination = termination;
+                    let mut run = scenario.run()?;
+                    let violations = batch.check(&mut run);
+                    Ok::<_, io::Error>((run.summary(), violations))
+                })
+                .expect("run batch")
+        };
+        let (local, common) = (report(CoinType::Local), report(CoinType::Common));
+        assert_eq!((local.unterminated, common.unterminated), (0, 0));
+        assert_eq!((local.unsafe_runs, common.unsafe_runs), (0, 0));
+        let mean = |report: &BatchReport| report.overall().expect("decided").mean;
+        assert!(mean(&common) < mean(&local));
+    }
+}
@@ -0,0 +1,225 @@
+use std::{error, fmt};
+
+use crate::{
+    message::{Phase, Value},
+    outcome::Outcome,
+    process::{Id, ProcessConfig},
+    step::Behavior,
+};
+
+/// A breach of one of the safety properties of consensus.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Violation {
+    /// Two correct processes, or a correct process at two phases, decided
+    /// different values.
+    Agreement {
+        first: (Id, Phase, Value),
+        second: (Id, Phase, Value),
+    },
+    /// A correct process decided a value that no correct process started
+    /// with.
+    Validity {
+        id: Id,
+        phase: Phase,
+        decided: Value,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::Agreement {
+                first: (first_id, first_phase, first_value),
+                second: (second_id, second_phase, second_value),
+            } => write!(
+                f,
+                "agreement: process {} decided {} in phase {} but process {} decided {} in phase {}",
+                first_id, first_value, first_phase.0, second_id, second_value, second_phase.0
+            ),
+            Violation::Validity { id, phase, decided } => write!(
+                f,
+                "validity: process {} decided {} in phase {} but no correct process started with it",
+                id, decided, phase.0
+            ),
+        }
+    }
+}
+
+impl error::Error for Violation {}
+
+/// Checks the outcomes of a run for agreement and validity.
+pub struct SafetyChecker {
+    // the initial values that a correct process may decide
+    valid: Vec<Value>,
+    correct: Vec<bool>,
+    first_decision: Option<(Id, Phase, Value)>,
+}
+
+impl SafetyChecker {
+    /// `inits[i]` is the initial value of process `i`, and `correct[i]` tells
+    /// whether it follows the algorithm.
+    pub fn new(inits: Vec<Value>, correct: Vec<bool>) -> Self {
+        assert_eq!(inits.len(), correct.len());
+        let valid = inits
+            .into_iter()
+            .zip(&correct)
+            .filter(|(_, correct)| **correct)
+            .map(|(init, _)| init)
+            .collect();
+        SafetyChecker {
+            valid,
+            correct,
+            first_decision: None,
+        }
+    }
+
+    /// A checker for the processes that `simulate` sets up.
+    pub fn for_simulation(
+        num_processes: usize,
+        num_zeros: usize,
+        num_adversaries: usize,
+        adversarial_behavior: &Behavior,
+    ) -> Self {
+        Self::for_processes(&ProcessConfig::for_simulation(
+            num_processes,
+            num_zeros,
+            num_adversaries,
+            adversarial_behavior,
+        ))
+    }
+
+    /// A checker for the given processes. Faulty processes that only ever
+    /// withhold messages started with a value as valid as that of any
+    /// correct process.
+    pub fn for_processes(processes: &[ProcessConfig]) -> Self {
+        let mut checker = Self::new(
+            processes.iter().map(|config| config.init.clone()).collect(),
+            processes.iter().map(ProcessConfig::is_correct).collect(),
+        );
+        checker.valid = processes
+            .iter()
+            .filter(|config| config.behavior.withholds_only())
+            .map(|config| config.init.clone())
+            .collect();
+        checker
+    }
+
+    pub fn observe(&mut self, id: &Id, outcome: &Outcome) -> Result<(), Violation> {
+        if !self.correct[id.0] {
+            return Ok(());
+        }
+        let decided = match outcome.decided() {
+            Some(decided) => decided,
+            None => return Ok(()),
+        };
+        let phase = outcome.phase();
+
+        if !self.valid.contains(&decided) {
+            return Err(Violation::Validity {
+                id: id.clone(),
+                phase,
+                decided,
+            });
+        }
+
+        match &self.first_decision {
+            None => {
+                self.first_decision = Some((id.clone(), phase, decided));
+                Ok(())
+            }
+            Some(first) if first.2 != decided => Err(Violation::Agreement {
+                first: first.clone(),
+                second: (id.clone(), phase, decided),
+            }),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Checks every outcome, returning all the violations found.
+    pub fn check<'a>(
+        &mut self,
+        outcomes: impl IntoIterator<Item = &'a (Id, Outcome)>,
+    ) -> Vec<Violation> {
+        outcomes
+            .into_iter()
+            .filter_map(|(id, outcome)| self.observe(id, outcome).err())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::outcome::Decision;
+
+    fn decided(id: usize, phase: u64, value: Value) -> (Id, Outcome) {
+        (
+            Id(id),
+            Outcome {
+                phase: Phase(phase),
+                decision: Decision::Done {
+                    next: value.clone(),
+                    decided: value,
+                },
+            },
+        )
+    }
+
+    fn pending(id: usize, phase: u64) -> (Id, Outcome) {
+        (
+            Id(id),
+            Outcome {
+                phase: Phase(phase),
+                decision: Decision::Pending { next: Value::One },
+            },
+        )
+    }
+
+    #[test]
+    fn agreement() {
+        let mut checker = SafetyChecker::new(vec![Value::Zero, Value::One], vec![true, true]);
+        assert_eq!(
+            checker.check(&[
+                pending(0, 0),
+                decided(0, 1, Value::One),
+                decided(1, 2, Value::One),
+                decided(1, 3, Value::Zero),
+            ]),
+            vec![Violation::Agreement {
+                first: (Id(0), Phase(1), Value::One),
+                second: (Id(1), Phase(3), Value::Zero),
+            }]
+        );
+    }
+
+    #[test]
+    fn faulty_processes_are_ignored() {
+        let mut checker = SafetyChecker::for_simulation(3, 1, 1, &Behavior::Crashes);
+        assert_eq!(
+            checker.check(&[decided(0, 1, Value::Zero), decided(1, 2, Value::One)]),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn validity() {
+        let mut checker = SafetyChecker::new(vec![Value::One, Value::Zero], vec![true, false]);
+        assert_eq!(
+            checker.check(&[decided(0, 4, Value::Zero)]),
+            vec![Violation::Validity {
+                id: Id(0),
+                phase: Phase(4),
+                decided: Value::Zero,
+            }]
+        );
+    }
+
+    #[test]
+    fn crashed_processes_started_with_valid_values() {
+        let mut checker = SafetyChecker::for_simulation(3, 1, 1, &Behavior::Crashes);
+        assert_eq!(checker.check(&[decided(1, 4, Value::Zero)]), vec![]);
+        let mut checker = SafetyChecker::for_simulation(3, 1, 1, &Behavior::SendsInvalidMessages);
+        assert_eq!(checker.check(&[decided(1, 4, Value::Zero)]).len(), 1);
+    }
+}
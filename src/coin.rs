@@ -0,0 +1,144 @@
+use std::{fmt, str::FromStr};
+
+use rand::{rngs::StdRng, Rng, RngCore, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::message::{Phase, Value};
+
+/// What a process falls back on for its next value when no proposal tells
+/// it which value to adopt.
+pub trait Coin: Send {
+    /// The value for `phase`, drawn from `rng` if the coin is the process's
+    /// own.
+    fn flip(&mut self, phase: Phase, rng: &mut dyn RngCore) -> Value;
+}
+
+/// Every process flips its own coin, so the correct processes only agree on
+/// a value by chance, and need exponentially many phases in expectation.
+pub struct LocalCoin;
+
+impl Coin for LocalCoin {
+    fn flip(&mut self, _phase: Phase, rng: &mut dyn RngCore) -> Value {
+        if rng.gen::<bool>() {
+            Value::One
+        } else {
+            Value::Zero
+        }
+    }
+}
+
+/// Every process sees the same flip in a phase, as if it came from a
+/// trusted dealer or a threshold signature, so that the correct processes
+/// agree within a constant number of phases in expectation. The flips are
+/// derived from the seed of the run, and are the same for every process
+/// given the same seed, but apart from the RNGs the processes draw from.
+pub struct CommonCoin {
+    rng: StdRng,
+    flips: Vec<Value>,
+}
+
+// the seeds of the processes' RNGs are drawn from an RNG seeded with the
+// seed of the run as it is, so the coin's is seeded with a salted one, lest
+// the flips be bits of those seeds
+const SALT: u64 = 0x636f_6d6d_6f6e_636f;
+
+impl CommonCoin {
+    pub fn new(seed: u64) -> Self {
+        CommonCoin {
+            rng: StdRng::seed_from_u64(seed ^ SALT),
+            flips: vec![],
+        }
+    }
+}
+
+impl Coin for CommonCoin {
+    fn flip(&mut self, phase: Phase, _rng: &mut dyn RngCore) -> Value {
+        // the flips of the earlier phases are drawn even if they weren't
+        // needed, so that they don't depend on which phases a process flipped
+        // in
+        while self.flips.len() <= phase.0 as usize {
+            let flip = LocalCoin.flip(phase, &mut self.rng);
+            self.flips.push(flip);
+        }
+        self.flips[phase.0 as usize].clone()
+    }
+}
+
+/// The coin the processes of a run flip.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoinType {
+    #[default]
+    Local,
+    Common,
+}
+
+impl CoinType {
+    /// The coin of a process in a run with the given seed.
+    pub fn coin(self, seed: u64) -> Box<dyn Coin> {
+        match self {
+            CoinType::Local => Box::new(LocalCoin),
+            CoinType::Common => Box::new(CommonCoin::new(seed)),
+        }
+    }
+}
+
+impl fmt::Display for CoinType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoinType::Local => write!(f, "local"),
+            CoinType::Common => write!(f, "common"),
+        }
+    }
+}
+
+impl FromStr for CoinType {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(CoinType::Local),
+            "common" => Ok(CoinType::Common),
+            _ => Err("invalid coin string"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_coins_agree_whatever_phases_they_flip_in() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut coins = (0..2).map(|_| CoinType::Common.coin(5)).collect::<Vec<_>>();
+        let late = coins[1].flip(Phase(9), &mut rng);
+        let flips = (0..10)
+            .map(|phase| coins[0].flip(Phase(phase), &mut rng))
+            .collect::<Vec<_>>();
+        assert_eq!(flips[9], late);
+        assert_eq!(
+            (0..10)
+                .map(|phase| coins[1].flip(Phase(phase), &mut rng))
+                .collect::<Vec<_>>(),
+            flips
+        );
+        assert!(flips.contains(&Value::Zero) && flips.contains(&Value::One));
+    }
+
+    #[test]
+    fn common_coins_flip_apart_from_the_processes() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut coin = CommonCoin::new(5);
+        let flips = (0..64)
+            .map(|phase| coin.flip(Phase(phase), &mut rng))
+            .collect::<Vec<_>>();
+        for id in 0..8 {
+            let mut process_rng = crate::process_rng(5, id);
+            let draws = (0..64)
+                .map(|phase| LocalCoin.flip(Phase(phase), &mut process_rng))
+                .collect::<Vec<_>>();
+            assert_ne!(flips, draws, "process {}", id);
+        }
+    }
+}
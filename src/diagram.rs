@@ -0,0 +1,282 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+};
+
+use crate::{
+    message::Value,
+    trace::{Event, TraceEvent},
+};
+
+// the layout, in pixels
+const MARGIN: usize = 40;
+const LANE_HEIGHT: usize = 60;
+const STEP: usize = 12;
+// how far a message that was never received gets drawn
+const LOST_LENGTH: usize = 24;
+
+// the id, legend and colour of each kind of message and value
+const STYLES: [(&str, &str, &str); 5] = [
+    ("R0", "(R, 0)", "#1f77b4"),
+    ("R1", "(R, 1)", "#ff7f0e"),
+    ("P0", "(P, 0)", "#2ca02c"),
+    ("P1", "(P, 1)", "#d62728"),
+    ("P-", "(P, ?)", "#7f7f7f"),
+];
+
+/// A space-time diagram of a traced run: one lane per process, with time
+/// flowing to the right in the order of the trace. Every message is an
+/// arrow from its send to its receive, coloured by its kind and value, and
+/// decisions are highlighted. Messages a process sends to itself are left
+/// out, and those that were never received end in a short stub.
+pub struct Diagram {
+    num_processes: usize,
+    num_steps: usize,
+    arrows: Vec<Arrow>,
+    marks: Vec<Mark>,
+    phases: Vec<(usize, usize, u64)>,
+}
+
+struct Arrow {
+    from: usize,
+    to: usize,
+    sent: usize,
+    received: Option<usize>,
+    style: &'static str,
+}
+
+struct Mark {
+    process: usize,
+    step: usize,
+    decided: bool,
+    value: Value,
+}
+
+impl Diagram {
+    pub fn new(events: &[TraceEvent]) -> Self {
+        let mut diagram = Diagram {
+            num_processes: 0,
+            num_steps: events.len(),
+            arrows: vec![],
+            marks: vec![],
+            phases: vec![],
+        };
+        // the sends not received yet, by sender, recipient, phase and style
+        let mut in_flight = HashMap::<_, VecDeque<usize>>::new();
+        let mut phases = HashMap::new();
+        for (step, traced) in events.iter().enumerate() {
+            let (process, phase) = (traced.process, traced.phase);
+            diagram.num_processes = diagram.num_processes.max(process + 1);
+            if phases.insert(process, phase) != Some(phase) {
+                diagram.phases.push((process, step, phase));
+            }
+            match &traced.event {
+                Event::Send { to, kind, value } if *to != process => {
+                    let style = style(*kind, value);
+                    diagram.num_processes = diagram.num_processes.max(to + 1);
+                    in_flight
+                        .entry((process, *to, phase, style))
+                        .or_default()
+                        .push_back(diagram.arrows.len());
+                    diagram.arrows.push(Arrow {
+                        from: process,
+                        to: *to,
+                        sent: step,
+                        received: None,
+                        style,
+                    });
+                }
+                Event::Receive { from, kind, value } if *from != process => {
+                    let sent = in_flight
+                        .get_mut(&(*from, process, phase, style(*kind, value)))
+                        .and_then(VecDeque::pop_front);
+                    if let Some(i) = sent {
+                        diagram.arrows[i].received = Some(step);
+                    }
+                }
+                Event::Coin { value } => diagram.marks.push(Mark {
+                    process,
+                    step,
+                    decided: false,
+                    value: value.clone(),
+                }),
+                Event::Decide { value } => diagram.marks.push(Mark {
+                    process,
+                    step,
+                    decided: true,
+                    value: value.clone(),
+                }),
+                _ => {}
+            }
+        }
+        diagram
+    }
+
+    /// Writes the diagram as a standalone SVG document.
+    pub fn write_svg(&self, mut writer: impl io::Write) -> io::Result<()> {
+        let width = 2 * MARGIN + (self.num_steps + 1) * STEP + LOST_LENGTH;
+        let height = 2 * MARGIN + self.num_processes * LANE_HEIGHT;
+        writeln!(
+            writer,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="sans-serif" font-size="10">"#,
+            width, height
+        )?;
+        writeln!(writer, "<defs>")?;
+        for (id, _, colour) in &STYLES {
+            writeln!(
+                writer,
+                r#"<marker id="{}" markerWidth="6" markerHeight="6" refX="6" refY="3" orient="auto"><path d="M0,0 L6,3 L0,6 z" fill="{}"/></marker>"#,
+                id, colour
+            )?;
+        }
+        writeln!(writer, "</defs>")?;
+        for (i, (_, legend, colour)) in STYLES.iter().enumerate() {
+            writeln!(
+                writer,
+                r#"<text x="{}" y="{}" fill="{}">{}</text>"#,
+                MARGIN + 40 * i,
+                MARGIN / 2,
+                colour,
+                legend
+            )?;
+        }
+        for process in 0..self.num_processes {
+            let y = lane(process);
+            writeln!(
+                writer,
+                r#"<text x="4" y="{}">{}</text><line x1="{}" y1="{}" x2="{}" y2="{}" stroke="black"/>"#,
+                y + 4,
+                process,
+                MARGIN,
+                y,
+                width - MARGIN,
+                y
+            )?;
+        }
+        for (process, step, phase) in &self.phases {
+            let (x, y) = (x(*step), lane(*process));
+            writeln!(
+                writer,
+                r##"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="#999"/><text x="{}" y="{}" fill="#999">{}</text>"##,
+                x,
+                y - 8,
+                x,
+                y + 8,
+                x + 2,
+                y - 10,
+                phase
+            )?;
+        }
+        for arrow in &self.arrows {
+            let colour = colour(arrow.style);
+            let (x1, y1) = (x(arrow.sent), lane(arrow.from));
+            let (x2, y2, opacity) = match arrow.received {
+                Some(received) => (x(received), lane(arrow.to), 1.0),
+                // point a lost message at its recipient, but only a little
+                None if arrow.to > arrow.from => (x1 + LOST_LENGTH, y1 + LOST_LENGTH, 0.4),
+                None => (x1 + LOST_LENGTH, y1 - LOST_LENGTH, 0.4),
+            };
+            writeln!(
+                writer,
+                r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="{}" stroke-opacity="{}" marker-end="url(#{})"/>"#,
+                x1, y1, x2, y2, colour, opacity, arrow.style
+            )?;
+        }
+        for mark in &self.marks {
+            let (fill, radius, label) = if mark.decided {
+                ("gold", 6, "decide")
+            } else {
+                ("white", 3, "coin")
+            };
+            writeln!(
+                writer,
+                r#"<circle cx="{}" cy="{}" r="{}" fill="{}" stroke="black"><title>{} {}</title></circle>"#,
+                x(mark.step),
+                lane(mark.process),
+                radius,
+                fill,
+                label,
+                mark.value
+            )?;
+        }
+        writeln!(writer, "</svg>")
+    }
+}
+
+fn style(kind: char, value: &Option<Value>) -> &'static str {
+    match (kind, value) {
+        ('R', Some(Value::Zero)) => STYLES[0].0,
+        ('R', _) => STYLES[1].0,
+        (_, Some(Value::Zero)) => STYLES[2].0,
+        (_, Some(Value::One)) => STYLES[3].0,
+        (_, None) => STYLES[4].0,
+    }
+}
+
+fn colour(style: &str) -> &'static str {
+    STYLES
+        .iter()
+        .find(|(id, _, _)| *id == style)
+        .map(|(_, _, colour)| *colour)
+        .expect("known style")
+}
+
+fn x(step: usize) -> usize {
+    MARGIN + (step + 1) * STEP
+}
+
+fn lane(process: usize) -> usize {
+    MARGIN + process * LANE_HEIGHT + LANE_HEIGHT / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(process: usize, event: Event) -> TraceEvent {
+        TraceEvent {
+            process,
+            phase: 1,
+            event,
+        }
+    }
+
+    #[test]
+    fn matches_receives_to_their_sends() {
+        let report = |to| Event::Send {
+            to,
+            kind: 'R',
+            value: Some(Value::One),
+        };
+        let events = vec![
+            event(0, report(0)),
+            event(0, report(1)),
+            event(0, report(2)),
+            event(
+                1,
+                Event::Receive {
+                    from: 0,
+                    kind: 'R',
+                    value: Some(Value::One),
+                },
+            ),
+            event(2, Event::Decide { value: Value::One }),
+        ];
+        let diagram = Diagram::new(&events);
+        assert_eq!(diagram.num_processes, 3);
+        assert_eq!(
+            diagram
+                .arrows
+                .iter()
+                .map(|arrow| (arrow.to, arrow.received))
+                .collect::<Vec<_>>(),
+            vec![(1, Some(3)), (2, None)]
+        );
+        let mut svg = vec![];
+        diagram.write_svg(&mut svg).expect("write");
+        let svg = String::from_utf8(svg).expect("utf-8");
+        assert!(svg.starts_with("<svg") && svg.ends_with("</svg>\n"));
+        assert!(svg.contains(r#"marker-end="url(#R1)""#));
+        assert!(svg.contains("<title>decide 1</title>"));
+    }
+}
@@ -1,53 +1,201 @@
 extern crate rand;
 
+mod batch;
+mod checker;
+mod coin;
+mod diagram;
 mod message;
+mod monitor;
+mod multi_valued;
 mod outcome;
 mod process;
+mod run;
+mod scenario;
+mod simulator;
 mod step;
+mod sweep;
+mod termination;
+mod trace;
 mod transport;
 
-use crate::{
-    message::Value,
-    outcome::Outcome,
-    process::{Id, Process},
-};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
-pub use crate::step::Behavior;
-pub use crate::transport::{ByteChannel, MessageChannel, Transport};
+use crate::{outcome::Outcomes, process::Process, step::Algorithm, trace::Recorder};
+
+pub use crate::batch::{Batch, BatchReport, Stats};
+pub use crate::checker::{SafetyChecker, Violation};
+pub use crate::coin::{Coin, CoinType, CommonCoin, LocalCoin};
+pub use crate::diagram::Diagram;
+pub use crate::message::{Message, Phase, Value};
+pub use crate::monitor::{Monitor, ProcessState};
+pub use crate::multi_valued::{
+    agree, simulate_agreement, Agreement, AgreementError, MAX_PAYLOAD_LEN,
+};
+pub use crate::outcome::{Outcome, Stall};
+pub use crate::process::{Id, ProcessConfig};
+pub use crate::run::Run;
+pub use crate::scenario::{ProcessGroup, Scenario, ScenarioError, ScenarioRun, TransportType};
+pub use crate::simulator::{
+    AdversarialScheduler, Envelope, FifoScheduler, RandomScheduler, Scheduler, Simulator,
+};
+pub use crate::step::{Behavior, Protocol};
+pub use crate::sweep::{Bound, Bounds, Op, Sweep};
+pub use crate::termination::{Summary, Termination, Tracker};
+pub use crate::trace::{Event, Trace, TraceEvent};
+pub use crate::transport::{
+    ByteChannel, CrossTraffic, Faults, FaultyTransport, Latency, MessageChannel, Partition,
+    PartitionSchedule, PartitionedTransport, Partitions, Stopper, TcpTransport, Transport,
+    TransportError,
+};
 
 pub fn simulate(
     num_zeros: usize,
     num_adversaries: usize,
     adversarial_behavior: Behavior,
     transports: Vec<Box<dyn Transport>>,
+    seed: u64,
+) -> Run {
+    simulate_until(
+        num_zeros,
+        num_adversaries,
+        adversarial_behavior,
+        transports,
+        seed,
+        Termination::default(),
+    )
+}
+
+/// Like `simulate`, but stops according to `termination`.
+pub fn simulate_until(
+    num_zeros: usize,
+    num_adversaries: usize,
+    adversarial_behavior: Behavior,
+    transports: Vec<Box<dyn Transport>>,
+    seed: u64,
+    termination: Termination,
+) -> Run {
+    let processes = ProcessConfig::for_simulation(
+        transports.len(),
+        num_zeros,
+        num_adversaries,
+        &adversarial_behavior,
+    );
+    Run::spawn(
+        processes,
+        num_adversaries,
+        Algorithm::default(),
+        transports,
+        seed,
+        termination,
+        None,
+    )
+}
+
+/// Like `simulate_until`, but with every process configured on its own. The
+/// correct processes tolerate up to `num_adversaries` faulty ones.
+pub fn simulate_processes(
+    processes: Vec<ProcessConfig>,
+    num_adversaries: usize,
+    transports: Vec<Box<dyn Transport>>,
+    seed: u64,
+    termination: Termination,
+) -> Run {
+    assert!(num_adversaries < processes.len());
+    Run::spawn(
+        processes,
+        num_adversaries,
+        Algorithm::default(),
+        transports,
+        seed,
+        termination,
+        None,
+    )
+}
+
+/// Runs the process with the given id on the calling thread, exactly as
+/// `simulate` would run it, so that a process can live in its own OS process.
+pub fn run_node(
+    id: usize,
+    num_zeros: usize,
+    num_adversaries: usize,
+    adversarial_behavior: &Behavior,
+    transport: Box<dyn Transport>,
+    seed: u64,
+    termination: &Termination,
+) -> impl Iterator<Item = (Id, Outcome)> {
+    let num_processes = transport.num_senders();
+    assert!(id < num_processes);
+    let mut processes = ProcessConfig::for_simulation(
+        num_processes,
+        num_zeros,
+        num_adversaries,
+        adversarial_behavior,
+    );
+    run_process(
+        id,
+        processes.swap_remove(id),
+        num_adversaries,
+        Algorithm::default(),
+        transport,
+        seed,
+        termination,
+        Recorder::off(id),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn run_process(
+    id: usize,
+    config: ProcessConfig,
+    num_adversaries: usize,
+    algorithm: Algorithm,
+    transport: Box<dyn Transport>,
+    seed: u64,
+    termination: &Termination,
+    recorder: Recorder,
 ) -> impl Iterator<Item = (Id, Outcome)> {
-    let mut processes = vec![];
-    for (i, transport) in transports.into_iter().enumerate() {
-        processes.push(Process {
-            id: Id(i),
-            transport,
-        })
-    }
-    let (sender, receiver) = std::sync::mpsc::channel();
-    let num_processes = processes.len();
-    for process in processes {
-        let sender = sender.clone();
-        assert!(num_zeros <= num_processes);
-        let init = if process.id.0 < num_zeros {
-            Value::Zero
-        } else {
-            Value::One
-        };
-        let step_fn = if process.id.0 < num_adversaries {
-            adversarial_behavior.step_fn()
-        } else {
-            Behavior::Correct.step_fn()
-        };
-        let _ = std::thread::spawn(move || {
-            for (id, outcome) in process.run(init, step_fn, num_adversaries) {
-                sender.send((id, outcome)).expect("send outcome");
-            }
-        });
-    }
-    receiver.into_iter()
+    let outcomes = start_process(
+        id,
+        config,
+        num_adversaries,
+        algorithm,
+        transport,
+        seed,
+        termination,
+        recorder,
+    );
+    termination.limit(outcomes.map(move |outcome| (Id(id), outcome)))
+}
+
+// the outcomes of a process, before the termination policy stops it
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn start_process(
+    id: usize,
+    config: ProcessConfig,
+    num_adversaries: usize,
+    algorithm: Algorithm,
+    transport: Box<dyn Transport>,
+    seed: u64,
+    termination: &Termination,
+    recorder: Recorder,
+) -> Outcomes<impl Iterator<Item = Phase>> {
+    let mut process = Process {
+        id: Id(id),
+        transport: recorder.wrap(transport),
+        rng: process_rng(seed, id),
+        stall_timeout: termination.stall_timeout(),
+        protocol: algorithm.protocol,
+        coin: algorithm.coin.coin(seed),
+        recorder,
+    };
+    let step_fn = config.behavior.step_fn(&mut process.rng);
+    process.run(config.init, step_fn, num_adversaries)
+}
+
+// every process gets its own RNG derived from the master seed so that
+// a run can be replayed given the same seed and message schedule
+fn process_rng(seed: u64, id: usize) -> StdRng {
+    let mut master = StdRng::seed_from_u64(seed);
+    let process_seed = (0..=id).map(|_| master.gen()).last().expect("at least one");
+    StdRng::seed_from_u64(process_seed)
 }
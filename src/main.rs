@@ -1,54 +1,994 @@
-use std::env;
+use std::{
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Write},
+    net::{SocketAddr, TcpListener},
+    process::{Child, Command, Stdio},
+    sync::mpsc::Receiver,
+};
 
-use ben_or_randomized_consensus::{simulate, Behavior, ByteChannel, MessageChannel, Transport};
+use ben_or_randomized_consensus::{
+    run_node, Batch, Behavior, Bound, Bounds, CoinType, Diagram, Id, Outcome, PartitionSchedule,
+    ProcessConfig, Protocol, SafetyChecker, Scenario, ScenarioError, ScenarioRun, Summary, Sweep,
+    TcpTransport, Termination, TraceEvent, Tracker, TransportType, Violation,
+};
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::filter::LevelFilter;
+
+mod tui;
 
 fn main() {
     let mut args: Vec<String> = env::args().collect();
     let bin_name = args.remove(0);
-    match parse(args) {
-        Ok((num_processes, num_zeros, num_adversaries, behavior, transport_type)) => {
-            for (id, outcome) in simulate(
-                num_zeros,
-                num_adversaries,
-                behavior,
-                transport(&transport_type, num_processes),
-            ) {
-                println!("Process {}: outcome: {}", id, outcome);
+    let result = parse_logging(&mut args).and_then(|logging| {
+        logging.init();
+        let termination = parse_termination(&mut args)?;
+        let trace = parse_trace(&mut args)?;
+        let protocol = parse_protocol(&mut args)?;
+        let coin = parse_coin(&mut args)?;
+        let partitions = parse_partitions(&mut args)?;
+        match args.first().map(String::as_str) {
+            Some("node" | "cluster" | "batch" | "sweep" | "diagram") if partitions.is_some() => {
+                Err(String::from(
+                    "--partition is only for run, tui and a single simulation",
+                ))
+            }
+            Some("node" | "cluster" | "batch" | "sweep" | "tui") if trace.is_some() => Err(
+                String::from("--trace is only for a single run in this process"),
+            ),
+            Some("node" | "cluster" | "batch" | "sweep") if protocol.is_some() => Err(
+                String::from("--protocol is only for run, tui and a single simulation"),
+            ),
+            Some("node" | "cluster" | "sweep") if coin.is_some() => Err(String::from(
+                "--coin is only for run, tui, batch and a single simulation",
+            )),
+            Some("diagram")
+                if trace.is_some()
+                    || protocol.is_some()
+                    || coin.is_some()
+                    || termination != Termination::default() =>
+            {
+                Err(String::from(
+                    "diagram only draws a trace that was already recorded",
+                ))
             }
+            Some("diagram") => parse_diagram(&args[1..]).map(diagram),
+            Some("node") => per_process(termination).and_then(|termination| {
+                parse_node(&args[1..]).map(|args| node(args, &termination))
+            }),
+            Some("cluster") => per_process(termination).and_then(|termination| {
+                parse_cluster(&args[1..]).map(|args| cluster(args, &termination, &logging))
+            }),
+            Some("batch") if termination != Termination::default() => Err(String::from(
+                "batch stops every run at <max phase> or once all correct processes decided",
+            )),
+            Some("batch") => parse_batch(args[1..].to_vec()).map(|(mut args, transport, csv)| {
+                args.coin = coin.unwrap_or_default();
+                batch((args, transport, csv))
+            }),
+            Some("sweep") if termination != Termination::default() => Err(String::from(
+                "sweep stops every run at max_phase or once all correct processes decided",
+            )),
+            Some("sweep") => parse_sweep(&args[1..]).map(sweep),
+            Some("run") => parse_run(&args[1..], termination, protocol, coin, partitions)
+                .map(|scenario| run(scenario, trace)),
+            Some("tui") => parse_run(&args[1..], termination, protocol, coin, partitions).map(tui),
+            _ => parse(args).and_then(
+                |(num_processes, num_zeros, num_adversaries, behavior, transport_type, seed)| {
+                    let mut scenario = Scenario::for_simulation(
+                        num_processes,
+                        num_zeros,
+                        num_adversaries,
+                        &behavior,
+                        transport_type,
+                    );
+                    scenario.seed = seed;
+                    scenario.termination = termination;
+                    scenario.protocol = protocol.unwrap_or_default();
+                    scenario.coin = coin.unwrap_or_default();
+                    scenario.partitions = partitions;
+                    scenario.validate().map_err(|e| e.to_string())?;
+                    run(scenario, trace);
+                    Ok(())
+                },
+            ),
         }
-        Err(e) => {
-            eprintln!("Error parsing args: {}", e);
-            eprintln!(
-                "Usage: {} <number of nodes> <number of zeros> <number of adversaries> <behavior> <transport type>",
-                bin_name
-            );
-            eprintln!(
-                "behavior: correct|crashes|sends_invalid_messages|stops_executing|randomly_adversarial",
-            );
-            eprintln!("transport type: message_channel|byte_channel",);
+    });
+    if let Err(e) = result {
+        eprintln!("Error parsing args: {}", e);
+        eprintln!(
+            "Usage: {} <number of nodes> <number of zeros> <number of adversaries> <behavior> <transport type> [seed]",
+            bin_name
+        );
+        eprintln!("       {} run --config <scenario file>", bin_name);
+        eprintln!(
+            "       {} run <processes> <transport type> [seed]",
+            bin_name
+        );
+        eprintln!(
+            "       {} cluster <number of nodes> <number of zeros> <number of adversaries> <behavior> [seed]",
+            bin_name
+        );
+        eprintln!(
+            "       {} batch <number of nodes> <number of zeros> <number of adversaries> <behavior> <transport type> <runs> <max phase> [seed] [--csv <file>]",
+            bin_name
+        );
+        eprintln!(
+            "       {} sweep n=<range> [f=<range>] [zeros=<range>] [behaviors=<behavior>,...] [transports=<transport type>,...] [coins=<coin>,...] [runs=<runs>] [max_phase=<max phase>] [seed=<seed>] [out=<file>]",
+            bin_name
+        );
+        eprintln!(
+            "       {} node <id> <addresses> <number of zeros> <number of adversaries> <behavior> <seed>",
+            bin_name
+        );
+        eprintln!(
+            "       {} tui --config <scenario file> | <processes> <transport type> [seed]",
+            bin_name
+        );
+        eprintln!("       {} diagram <trace file> [<svg file>]", bin_name);
+        eprintln!(
+            "options: --stop-after-decision <phases> --max-phase <phase> --stall-timeout <ms> --until-all-decided (not for node and cluster), overriding the scenario file",
+        );
+        eprintln!(
+            "trace: --trace <file> writes every send, receive, drop, skip, wait, coin flip and decision as JSON Lines (not for node, cluster, batch, sweep and tui)",
+        );
+        eprintln!(
+            "protocol: --protocol ben_or|ben_or_byzantine (ben_or by default, ben_or_byzantine needs more than 5 times as many processes as adversaries; not for node, cluster, batch and sweep), overriding the scenario file",
+        );
+        eprintln!(
+            "coin: --coin local|common (local by default; not for node, cluster and sweep), overriding the scenario file",
+        );
+        eprintln!(
+            "partitions: --partition <partition>, once per partition, --cross-traffic buffer|drop (buffer by default) --heal-after <ms> (1000 by default), only for run, tui and a single simulation on a threaded transport, overriding the scenario file",
+        );
+        eprintln!(
+            "logging: --log-level trace|debug|info|warn|error|off (info by default, off for tui) --log-format text|json, to stderr",
+        );
+        eprintln!(
+            "behavior: correct|crashes|sends_invalid_messages|sends_random_bytes|stops_executing|randomly_adversarial|equivocates",
+        );
+        eprintln!(
+            "transport type: message_channel|byte_channel|tcp|discrete_fifo|discrete_random|discrete_adversarial",
+        );
+        eprintln!(
+            "processes: comma separated list of <initial value>[:<behavior>][*<count>], e.g. 0*2,1:crashes,1*2",
+        );
+        eprintln!(
+            "range: inclusive range of numbers or expressions on n like 0..n/2, n-1 or 4..32",
+        );
+        eprintln!(
+            "partition: groups of processes split by | for a range of phases, e.g. 0,1|2..4@1..3",
+        );
+        eprintln!("addresses: comma separated list of ip:port, one per node",);
+        eprintln!(
+            "key: node proves its id to the others with the key in {} in hex, which they all share, unless cluster hands one out",
+            KEY_VAR
+        );
+    }
+}
+
+// how much of what the processes do goes to stderr, and in what format
+struct Logging {
+    level: LevelFilter,
+    json: bool,
+}
+
+impl Logging {
+    fn init(&self) {
+        let builder = tracing_subscriber::fmt()
+            .with_max_level(self.level)
+            .with_writer(io::stderr)
+            .with_ansi(io::stderr().is_terminal());
+        if self.json {
+            builder.json().init();
+        } else {
+            builder.init();
+        }
+    }
+
+    fn to_args(&self) -> Vec<String> {
+        let mut args = vec![String::from("--log-level"), self.level.to_string()];
+        if self.json {
+            args.push(String::from("--log-format"));
+            args.push(String::from("json"));
         }
+        args
     }
 }
 
-fn parse(args: Vec<String>) -> Result<(usize, usize, usize, Behavior, String), String> {
-    if args.len() != 5 {
-        return Err(String::from("need exactly 5 args"));
+// takes the logging options out of the args, wherever they appear; the
+// terminal UI logs nothing unless asked to, as the log would garble it
+fn parse_logging(args: &mut Vec<String>) -> Result<Logging, String> {
+    let mut level = None;
+    let mut json = false;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--log-level" | "--log-format" => {
+                let flag = args.remove(i);
+                if i == args.len() {
+                    return Err(format!("{} needs a value", flag));
+                }
+                let value = args.remove(i);
+                if flag == "--log-level" {
+                    level = Some(value.parse().map_err(|e| format!("{}", e))?);
+                } else {
+                    json = match value.as_str() {
+                        "text" => false,
+                        "json" => true,
+                        _ => return Err(String::from("--log-format must be text or json")),
+                    };
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    let level = level.unwrap_or(match args.first().map(String::as_str) {
+        Some("tui") => LevelFilter::OFF,
+        _ => LevelFilter::INFO,
+    });
+    Ok(Logging { level, json })
+}
+
+type Args = (usize, usize, usize, Behavior, TransportType, Option<u64>);
+
+fn parse(args: Vec<String>) -> Result<Args, String> {
+    if args.len() != 5 && args.len() != 6 {
+        return Err(String::from("need 5 or 6 args"));
     }
 
-    let parse_usize = |s: &str| s.parse().map_err(|e| format!("{}", e));
     Ok((
         parse_usize(&args[0])?,
         parse_usize(&args[1])?,
         parse_usize(&args[2])?,
         args[3].parse()?,
-        args[4].clone(),
+        args[4].parse()?,
+        parse_seed(args.get(5))?,
     ))
 }
 
-fn transport(transport_type: &str, num_processes: usize) -> Vec<Box<dyn Transport>> {
-    match transport_type {
-        "message_channel" => MessageChannel::new(num_processes),
-        "byte_channel" => ByteChannel::new(num_processes),
-        _ => panic!("invalid transport type string"),
+fn parse_run(
+    args: &[String],
+    termination: Termination,
+    protocol: Option<Protocol>,
+    coin: Option<CoinType>,
+    partitions: Option<PartitionSchedule>,
+) -> Result<Scenario, String> {
+    let mut scenario = match args.first().map(String::as_str) {
+        Some("--config") if args.len() == 2 => {
+            Scenario::load(&args[1]).map_err(|e| format!("{}: {}", args[1], e))?
+        }
+        Some(processes) if args.len() == 2 || args.len() == 3 => Scenario {
+            processes: processes
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<_, _>>()?,
+            num_adversaries: None,
+            protocol: Protocol::default(),
+            coin: CoinType::default(),
+            transport: args[1].parse()?,
+            seed: parse_seed(args.get(2))?,
+            faults: None,
+            partitions: None,
+            termination: Termination::default(),
+        },
+        _ => {
+            return Err(String::from(
+                "run needs --config <scenario file> or <processes> <transport type> [seed]",
+            ))
+        }
+    };
+    if termination != Termination::default() {
+        scenario.termination = termination;
+    }
+    if let Some(protocol) = protocol {
+        scenario.protocol = protocol;
+    }
+    if let Some(coin) = coin {
+        scenario.coin = coin;
+    }
+    if partitions.is_some() {
+        scenario.partitions = partitions;
+    }
+    scenario.validate().map_err(|e| e.to_string())?;
+    Ok(scenario)
+}
+
+type NodeArgs = (usize, Vec<SocketAddr>, usize, usize, Behavior, u64);
+
+fn parse_node(args: &[String]) -> Result<NodeArgs, String> {
+    if args.len() != 6 {
+        return Err(String::from("node needs exactly 6 args"));
+    }
+
+    let addrs = args[1]
+        .split(',')
+        .map(|s| s.parse().map_err(|e| format!("{}: {}", s, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let id = parse_usize(&args[0])?;
+    if id >= addrs.len() {
+        return Err(String::from(
+            "node needs an id less than the number of addresses",
+        ));
+    }
+    let num_zeros = parse_usize(&args[2])?;
+    let num_adversaries = parse_usize(&args[3])?;
+    check_nodes("node", addrs.len(), num_zeros, num_adversaries)?;
+    Ok((
+        id,
+        addrs,
+        num_zeros,
+        num_adversaries,
+        args[4].parse()?,
+        args[5].parse().map_err(|e| format!("{}", e))?,
+    ))
+}
+
+type ClusterArgs = (usize, usize, usize, Behavior, Option<u64>);
+
+fn parse_cluster(args: &[String]) -> Result<ClusterArgs, String> {
+    if args.len() != 4 && args.len() != 5 {
+        return Err(String::from("cluster needs 4 or 5 args"));
+    }
+
+    let num_processes = parse_usize(&args[0])?;
+    let num_zeros = parse_usize(&args[1])?;
+    let num_adversaries = parse_usize(&args[2])?;
+    check_nodes("cluster", num_processes, num_zeros, num_adversaries)?;
+    Ok((
+        num_processes,
+        num_zeros,
+        num_adversaries,
+        args[3].parse()?,
+        parse_seed(args.get(4))?,
+    ))
+}
+
+// nodes always run ben_or, since --protocol is not for node and cluster
+fn check_nodes(
+    subcommand: &str,
+    num_processes: usize,
+    num_zeros: usize,
+    num_adversaries: usize,
+) -> Result<(), String> {
+    if !Protocol::BenOr.tolerates(num_processes, num_adversaries) {
+        return Err(format!("{} needs more nodes than adversaries", subcommand));
+    }
+    if num_zeros > num_processes {
+        return Err(format!(
+            "{} needs at most as many zeros as nodes",
+            subcommand
+        ));
+    }
+    Ok(())
+}
+
+// takes the termination options out of the args, wherever they appear
+fn parse_termination(args: &mut Vec<String>) -> Result<Termination, String> {
+    let mut termination = Termination::default();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--stop-after-decision" | "--max-phase" | "--stall-timeout" => {
+                let flag = args.remove(i);
+                if i == args.len() {
+                    return Err(format!("{} needs a value", flag));
+                }
+                let value = args.remove(i).parse().map_err(|e| format!("{}", e))?;
+                match flag.as_str() {
+                    "--max-phase" => termination.max_phase = Some(value),
+                    "--stall-timeout" => termination.stall_timeout_ms = Some(value),
+                    _ => termination.extra_phases_after_decision = Some(value),
+                }
+            }
+            "--until-all-decided" => {
+                args.remove(i);
+                termination.all_correct_decided = true;
+            }
+            _ => i += 1,
+        }
+    }
+    Ok(termination)
+}
+
+// a node only knows about itself, so it can't tell when everyone decided
+fn per_process(termination: Termination) -> Result<Termination, String> {
+    if termination.all_correct_decided {
+        return Err(String::from(
+            "--until-all-decided is not supported by node and cluster",
+        ));
+    }
+    Ok(termination)
+}
+
+fn to_args(termination: &Termination) -> Vec<String> {
+    let mut args = vec![];
+    if let Some(extra) = termination.extra_phases_after_decision {
+        args.push(String::from("--stop-after-decision"));
+        args.push(extra.to_string());
+    }
+    if let Some(max_phase) = termination.max_phase {
+        args.push(String::from("--max-phase"));
+        args.push(max_phase.to_string());
+    }
+    if let Some(stall_timeout) = termination.stall_timeout_ms {
+        args.push(String::from("--stall-timeout"));
+        args.push(stall_timeout.to_string());
+    }
+    args
+}
+
+// takes the trace file out of the args, wherever it appears
+fn parse_trace(args: &mut Vec<String>) -> Result<Option<String>, String> {
+    match args.iter().position(|arg| arg == "--trace") {
+        Some(i) if i + 1 < args.len() => {
+            args.remove(i);
+            Ok(Some(args.remove(i)))
+        }
+        Some(_) => Err(String::from("--trace needs a file")),
+        None => Ok(None),
+    }
+}
+
+// takes the protocol out of the args, wherever it appears
+fn parse_protocol(args: &mut Vec<String>) -> Result<Option<Protocol>, String> {
+    match args.iter().position(|arg| arg == "--protocol") {
+        Some(i) if i + 1 < args.len() => {
+            args.remove(i);
+            Ok(Some(args.remove(i).parse()?))
+        }
+        Some(_) => Err(String::from("--protocol needs a protocol")),
+        None => Ok(None),
+    }
+}
+
+// takes the coin out of the args, wherever it appears
+fn parse_coin(args: &mut Vec<String>) -> Result<Option<CoinType>, String> {
+    match args.iter().position(|arg| arg == "--coin") {
+        Some(i) if i + 1 < args.len() => {
+            args.remove(i);
+            Ok(Some(args.remove(i).parse()?))
+        }
+        Some(_) => Err(String::from("--coin needs a coin")),
+        None => Ok(None),
+    }
+}
+
+// takes the partitions out of the args, wherever they appear
+fn parse_partitions(args: &mut Vec<String>) -> Result<Option<PartitionSchedule>, String> {
+    let mut schedule = vec![];
+    let mut cross_traffic = None;
+    let mut heal_after_ms = None;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--partition" | "--cross-traffic" | "--heal-after" => {
+                let flag = args.remove(i);
+                if i == args.len() {
+                    return Err(format!("{} needs a value", flag));
+                }
+                let value = args.remove(i);
+                match flag.as_str() {
+                    "--partition" => schedule.push(value.parse()?),
+                    "--cross-traffic" => cross_traffic = Some(value.parse()?),
+                    _ => heal_after_ms = Some(value.parse().map_err(|e| format!("{}", e))?),
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    if schedule.is_empty() {
+        if cross_traffic.is_some() || heal_after_ms.is_some() {
+            return Err(String::from(
+                "--cross-traffic and --heal-after need a --partition",
+            ));
+        }
+        return Ok(None);
+    }
+    Ok(Some(PartitionSchedule {
+        schedule,
+        cross_traffic: cross_traffic.unwrap_or_default(),
+        heal_after_ms: heal_after_ms.unwrap_or(PartitionSchedule::DEFAULT_HEAL_AFTER_MS),
+    }))
+}
+
+type DiagramArgs = (String, Option<String>);
+
+fn parse_diagram(args: &[String]) -> Result<DiagramArgs, String> {
+    match args {
+        [trace] => Ok((trace.clone(), None)),
+        [trace, svg] => Ok((trace.clone(), Some(svg.clone()))),
+        _ => Err(String::from("diagram needs 1 or 2 args")),
+    }
+}
+
+type BatchArgs = (Batch, TransportType, Option<String>);
+
+fn parse_batch(mut args: Vec<String>) -> Result<BatchArgs, String> {
+    let csv = match args.iter().position(|arg| arg == "--csv") {
+        Some(i) if i + 1 < args.len() => {
+            args.remove(i);
+            Some(args.remove(i))
+        }
+        Some(_) => return Err(String::from("--csv needs a file")),
+        None => None,
+    };
+    if args.len() != 7 && args.len() != 8 {
+        return Err(String::from("batch needs 7 or 8 args"));
+    }
+
+    let batch = Batch {
+        num_processes: parse_usize(&args[0])?,
+        num_zeros: parse_usize(&args[1])?,
+        num_adversaries: parse_usize(&args[2])?,
+        behavior: args[3].parse()?,
+        coin: CoinType::Local,
+        runs: parse_usize(&args[5])?,
+        max_phase: args[6].parse().map_err(|e| format!("{}", e))?,
+        seed: parse_seed(args.get(7))?.unwrap_or_else(rand::random),
+    };
+    // a batch always runs ben_or, since --protocol is not for batch
+    if !Protocol::BenOr.tolerates(batch.num_processes, batch.num_adversaries) {
+        return Err(String::from("batch needs more nodes than adversaries"));
+    }
+    if batch.num_zeros > batch.num_processes {
+        return Err(String::from("batch needs at most as many zeros as nodes"));
+    }
+    Ok((batch, args[4].parse()?, csv))
+}
+
+type SweepArgs = (Sweep, Option<String>);
+
+fn parse_sweep(args: &[String]) -> Result<SweepArgs, String> {
+    let mut sweep = Sweep {
+        num_processes: Bounds {
+            first: Bound::Fixed(0),
+            last: Bound::Fixed(0),
+        },
+        num_adversaries: "0".parse()?,
+        num_zeros: "n/2".parse()?,
+        behaviors: vec![Behavior::RandomlyAdversarial],
+        transports: vec![TransportType::DiscreteRandom],
+        coins: vec![CoinType::Local],
+        runs: 10,
+        max_phase: 100,
+        seed: rand::random(),
+    };
+    let mut has_num_processes = false;
+    let mut out = None;
+    for arg in args {
+        let mut parts = arg.splitn(2, '=');
+        let key = parts.next().unwrap_or_default();
+        let value = parts
+            .next()
+            .ok_or_else(|| format!("{}: expected <name>=<value>", arg))?;
+        match key {
+            "n" => {
+                sweep.num_processes = value.parse()?;
+                has_num_processes = true;
+            }
+            "f" => sweep.num_adversaries = value.parse()?,
+            "zeros" => sweep.num_zeros = value.parse()?,
+            "behaviors" => {
+                sweep.behaviors = value.split(',').map(str::parse).collect::<Result<_, _>>()?
+            }
+            "transports" => {
+                sweep.transports = value.split(',').map(str::parse).collect::<Result<_, _>>()?
+            }
+            "coins" => sweep.coins = value.split(',').map(str::parse).collect::<Result<_, _>>()?,
+            "runs" => sweep.runs = parse_usize(value)?,
+            "max_phase" => sweep.max_phase = value.parse().map_err(|e| format!("{}", e))?,
+            "seed" => sweep.seed = value.parse().map_err(|e| format!("{}", e))?,
+            "out" => out = Some(value.to_string()),
+            _ => return Err(format!("unknown sweep parameter {}", key)),
+        }
+    }
+    if !has_num_processes {
+        return Err(String::from("sweep needs n=<range>"));
+    }
+    let depends_on_n = |bound| matches!(bound, Bound::Processes(..));
+    if depends_on_n(sweep.num_processes.first) || depends_on_n(sweep.num_processes.last) {
+        return Err(String::from("n can't depend on itself"));
+    }
+    Ok((sweep, out))
+}
+
+fn parse_usize(s: &str) -> Result<usize, String> {
+    s.parse().map_err(|e| format!("{}", e))
+}
+
+fn parse_seed(s: Option<&String>) -> Result<Option<u64>, String> {
+    match s {
+        Some(s) => Ok(Some(s.parse().map_err(|e| format!("{}", e))?)),
+        None => Ok(None),
+    }
+}
+
+// runs a single seed of a batch to completion
+fn run_once(
+    batch: &Batch,
+    transport_type: TransportType,
+    seed: u64,
+    termination: Termination,
+) -> Result<(Summary, Vec<Violation>), ScenarioError> {
+    let mut scenario = Scenario::for_simulation(
+        batch.num_processes,
+        batch.num_zeros,
+        batch.num_adversaries,
+        &batch.behavior,
+        transport_type,
+    );
+    scenario.seed = Some(seed);
+    scenario.termination = termination;
+    scenario.coin = batch.coin;
+    scenario.validate()?;
+    let mut run = scenario.run()?;
+    let violations = batch.check(&mut run);
+    Ok((run.summary(), violations))
+}
+
+fn batch((batch, transport_type, csv): BatchArgs) {
+    eprintln!("Seed: {}", batch.seed);
+    let report = match batch
+        .run_with(|seed, termination| run_once(&batch, transport_type, seed, termination))
+    {
+        Ok(report) => report,
+        Err(e) => return eprintln!("Failed to run batch: {}", e),
+    };
+    print!("{}", report);
+    if let Some(path) = csv {
+        if let Err(e) = File::create(&path).and_then(|file| report.write_csv(file)) {
+            eprintln!("Failed to write {}: {}", path, e);
+        }
+    }
+}
+
+fn sweep((sweep, out): SweepArgs) {
+    eprintln!("Seed: {}", sweep.seed);
+    let result = match out {
+        Some(path) => File::create(&path)
+            .map_err(ScenarioError::Io)
+            .and_then(|file| sweep.run_with(run_once, file)),
+        None => sweep.run_with(run_once, io::stdout()),
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to run sweep: {}", e);
+    }
+}
+
+fn run(mut scenario: Scenario, trace: Option<String>) {
+    let seed = *scenario.seed.get_or_insert_with(rand::random);
+    eprintln!("Seed: {}", seed);
+    let path = match trace {
+        Some(path) => path,
+        None => {
+            match scenario.run() {
+                Ok(run) => report(run, scenario.checker()),
+                Err(e) => eprintln!("Failed to start run: {}", e),
+            }
+            return;
+        }
+    };
+    let file = match File::create(&path) {
+        Ok(file) => file,
+        Err(e) => return eprintln!("Failed to write {}: {}", path, e),
+    };
+    match scenario.run_traced() {
+        Ok((run, trace)) => {
+            // the trace is written while the run goes on, and is complete
+            // once the run is dropped
+            let writer = std::thread::spawn(move || trace.stream_jsonl(BufWriter::new(file)));
+            report(run, scenario.checker());
+            match writer.join() {
+                Ok(Err(e)) => eprintln!("Failed to write {}: {}", path, e),
+                Err(_) => eprintln!("Failed to write {}", path),
+                Ok(Ok(())) => {}
+            }
+        }
+        Err(e) => eprintln!("Failed to start run: {}", e),
+    }
+}
+
+// shows the processes of a run live in the terminal
+fn tui(mut scenario: Scenario) {
+    let seed = *scenario.seed.get_or_insert_with(rand::random);
+    eprintln!("Seed: {}", seed);
+    if let Err(e) = tui::show(&scenario) {
+        eprintln!("Failed to run the terminal UI: {}", e);
+    }
+}
+
+// draws a trace written by --trace, to stdout unless given a file
+fn diagram((trace, svg): DiagramArgs) {
+    let events =
+        match File::open(&trace).and_then(|file| TraceEvent::read_jsonl(BufReader::new(file))) {
+            Ok(events) => events,
+            Err(e) => return eprintln!("Failed to read {}: {}", trace, e),
+        };
+    let diagram = Diagram::new(&events);
+    let written = match &svg {
+        Some(path) => File::create(path).and_then(|file| diagram.write_svg(BufWriter::new(file))),
+        None => diagram.write_svg(io::stdout().lock()),
+    };
+    if let Err(e) = written {
+        eprintln!(
+            "Failed to write {}: {}",
+            svg.as_deref().unwrap_or("diagram"),
+            e
+        );
+    }
+}
+
+fn report(mut run: ScenarioRun, checker: SafetyChecker) {
+    check(&mut run, checker);
+    print!("{}", run.summary());
+}
+
+// prints every outcome as it comes, along with the safety violations it shows
+fn check(outcomes: impl Iterator<Item = (Id, Outcome)>, mut checker: SafetyChecker) {
+    for (id, outcome) in outcomes {
+        println!("Process {}: outcome: {}", id, outcome);
+        if let Err(violation) = checker.observe(&id, &outcome) {
+            println!("Safety violation: {}", violation);
+        }
+    }
+}
+
+// what a node launched by `cluster` tells it, one JSON object per line
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum NodeReport {
+    Listening,
+    Outcome { id: Id, outcome: Outcome },
+}
+
+impl NodeReport {
+    fn print(&self) {
+        if let Ok(line) = serde_json::to_string(self) {
+            println!("{}", line);
+        }
+    }
+}
+
+// the variable with the key a node proves its id with, unless `cluster`
+// hands one out
+const KEY_VAR: &str = "BEN_OR_KEY";
+
+fn node(
+    (id, addrs, num_zeros, num_adversaries, behavior, seed): NodeArgs,
+    termination: &Termination,
+) {
+    // when launched by `cluster`, stdin is a pipe from the launcher: the node
+    // reads the key of the cluster from it, tells it once it's bound, waits
+    // for it to start every node, and doesn't outlive it
+    let launched = !io::stdin().is_terminal();
+    let key = if launched {
+        let mut line = String::new();
+        io::stdin()
+            .read_line(&mut line)
+            .map_err(|e| e.to_string())
+            .and_then(|_| parse_key(line.trim_end()))
+    } else {
+        env::var(KEY_VAR)
+            .map_err(|e| format!("{}: {}", KEY_VAR, e))
+            .and_then(|key| parse_key(&key))
+    };
+    let key = match key {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("Failed to read the key: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let transport = match TcpTransport::new(id, addrs, &key) {
+        Ok(transport) => transport,
+        Err(e) => {
+            eprintln!("Failed to bind: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if launched {
+        NodeReport::Listening.print();
+        if !matches!(io::stdin().read_line(&mut String::new()), Ok(len) if len > 0) {
+            return;
+        }
+        std::thread::spawn(|| {
+            let _ = io::stdin().read_to_end(&mut vec![]);
+            std::process::exit(0);
+        });
+    }
+    for (id, outcome) in run_node(
+        id,
+        num_zeros,
+        num_adversaries,
+        &behavior,
+        Box::new(transport),
+        seed,
+        termination,
+    ) {
+        if launched {
+            NodeReport::Outcome { id, outcome }.print();
+        } else {
+            println!("Process {}: outcome: {}", id, outcome);
+        }
+    }
+}
+
+// a key in hex, as `cluster` hands it out
+fn parse_key(hex: &str) -> Result<Vec<u8>, String> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit())
+    {
+        return Err(String::from("the key must be an even number of hex digits"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|e| format!("{}", e)))
+        .collect()
+}
+
+// how many times `cluster` picks ports for its nodes, as another process may
+// take one of them before its node binds it
+const LAUNCH_ATTEMPTS: usize = 3;
+
+fn cluster(
+    (num_processes, num_zeros, num_adversaries, behavior, seed): ClusterArgs,
+    termination: &Termination,
+    logging: &Logging,
+) {
+    let seed = seed.unwrap_or_else(rand::random);
+    eprintln!("Seed: {}", seed);
+
+    let key: [u8; 32] = rand::random();
+    let key = key
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    let launch = || {
+        Nodes::launch(num_processes, &key, |id, addrs| {
+            let mut command = Command::new(env::current_exe()?);
+            command
+                .arg("node")
+                .arg(id.to_string())
+                .arg(addrs)
+                .arg(num_zeros.to_string())
+                .arg(num_adversaries.to_string())
+                .arg(behavior.to_string())
+                .arg(seed.to_string())
+                .args(to_args(termination))
+                .args(logging.to_args());
+            Ok(command)
+        })
+    };
+    let mut nodes = None;
+    for _ in 0..LAUNCH_ATTEMPTS {
+        match launch() {
+            Ok(Some(launched)) => {
+                nodes = Some(launched);
+                break;
+            }
+            Ok(None) => eprintln!("A node failed to start, launching the nodes again"),
+            Err(e) => return eprintln!("Failed to launch the nodes: {}", e),
+        }
+    }
+    let mut nodes = match nodes {
+        Some(nodes) => nodes,
+        None => return eprintln!("Failed to launch the nodes"),
+    };
+    if let Err(e) = nodes.start() {
+        return eprintln!("Failed to start the nodes: {}", e);
+    }
+
+    let processes =
+        ProcessConfig::for_simulation(num_processes, num_zeros, num_adversaries, &behavior);
+    let mut tracker = Tracker::new(
+        termination.clone(),
+        processes.iter().map(ProcessConfig::is_correct).collect(),
+    );
+    check(
+        nodes
+            .outcomes()
+            .inspect(|(id, outcome)| tracker.observe(id, outcome)),
+        SafetyChecker::for_processes(&processes),
+    );
+    print!("{}", tracker.summary());
+}
+
+// the nodes of a cluster, killed when dropped
+struct Nodes {
+    children: Vec<Child>,
+    // what each node reports, and `None` once it's gone
+    reports: Receiver<(usize, Option<NodeReport>)>,
+}
+
+impl Nodes {
+    // launches a node per process on ports the OS picks, hands each the key
+    // in hex, and waits for them all to bind their ports, or returns `None`
+    // if one of them fails to
+    fn launch(
+        num_processes: usize,
+        key: &str,
+        command: impl Fn(usize, &str) -> io::Result<Command>,
+    ) -> io::Result<Option<Self>> {
+        let listeners = (0..num_processes)
+            .map(|_| TcpListener::bind("127.0.0.1:0"))
+            .collect::<io::Result<Vec<_>>>()?;
+        let addrs = listeners
+            .iter()
+            .map(|listener| Ok(listener.local_addr()?.to_string()))
+            .collect::<io::Result<Vec<_>>>()?
+            .join(",");
+        drop(listeners);
+
+        let (sender, reports) = std::sync::mpsc::channel();
+        let mut nodes = Nodes {
+            children: vec![],
+            reports,
+        };
+        for id in 0..num_processes {
+            let mut child = command(id, &addrs)?
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()?;
+            let stdout = child.stdout.take();
+            nodes.children.push(child);
+            if let Some(stdin) = &mut nodes.children[id].stdin {
+                writeln!(stdin, "{}", key)?;
+            }
+            let sender = sender.clone();
+            std::thread::spawn(move || {
+                for line in stdout
+                    .into_iter()
+                    .flat_map(|out| BufReader::new(out).lines())
+                {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(_) => break,
+                    };
+                    match serde_json::from_str(&line) {
+                        Ok(report) => {
+                            let _ = sender.send((id, Some(report)));
+                        }
+                        Err(_) => eprintln!("Node {}: {}", id, line),
+                    }
+                }
+                let _ = sender.send((id, None));
+            });
+        }
+
+        let mut listening = 0;
+        while listening < num_processes {
+            match nodes.reports.recv() {
+                Ok((_, Some(NodeReport::Listening))) => listening += 1,
+                Ok((_, Some(NodeReport::Outcome { .. }))) => {}
+                Ok((_, None)) | Err(_) => return Ok(None),
+            }
+        }
+        Ok(Some(nodes))
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        for child in &mut self.children {
+            if let Some(stdin) = &mut child.stdin {
+                writeln!(stdin)?;
+            }
+        }
+        Ok(())
+    }
+
+    // the outcomes the nodes report, until they're all gone
+    fn outcomes(&self) -> impl Iterator<Item = (Id, Outcome)> + '_ {
+        self.reports.iter().filter_map(|(_, report)| match report {
+            Some(NodeReport::Outcome { id, outcome }) => Some((id, outcome)),
+            _ => None,
+        })
+    }
+}
+
+impl Drop for Nodes {
+    fn drop(&mut self) {
+        for child in &mut self.children {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
     }
 }
@@ -3,10 +3,61 @@ use std::{
     fmt, iter,
 };
 
+use serde::{Deserialize, Serialize};
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Message {
-    Proposal { phase: Phase, value: Option<Value> },
-    Report { phase: Phase, value: Value },
+    Proposal {
+        phase: Phase,
+        value: Option<Value>,
+    },
+    Report {
+        phase: Phase,
+        value: Value,
+    },
+    /// A payload to agree on in multi-valued consensus, as proposed by
+    /// `proposer`, or relayed for it by another process.
+    Candidate {
+        proposer: usize,
+        payload: Vec<u8>,
+    },
+    /// A report or proposal of one of the binary instances that multi-valued
+    /// consensus runs one after another.
+    Instance {
+        instance: u64,
+        message: Box<Message>,
+    },
+    /// The sender decided `value` in `phase` of a binary instance, and won't
+    /// send any more of its messages.
+    Decided {
+        instance: u64,
+        phase: Phase,
+        value: Value,
+    },
+}
+
+impl Message {
+    /// The phase of a binary message, or of the one wrapped in an instance.
+    /// Candidates belong to no phase.
+    pub(crate) fn phase(&self) -> Option<Phase> {
+        match self {
+            Message::Proposal { phase, .. }
+            | Message::Report { phase, .. }
+            | Message::Decided { phase, .. } => Some(*phase),
+            Message::Candidate { .. } => None,
+            Message::Instance { message, .. } => message.phase(),
+        }
+    }
+
+    /// The binary value a message carries, if any.
+    pub(crate) fn value(&self) -> Option<&Value> {
+        match self {
+            Message::Proposal { value, .. } => value.as_ref(),
+            Message::Report { value, .. } | Message::Decided { value, .. } => Some(value),
+            Message::Candidate { .. } => None,
+            Message::Instance { message, .. } => message.value(),
+        }
+    }
 }
 
 impl From<Message> for Vec<u8> {
@@ -32,6 +83,29 @@ impl From<Message> for Vec<u8> {
                 bytes.push(value.into());
                 bytes
             }
+            Message::Candidate { proposer, payload } => {
+                let mut bytes = vec![3];
+                bytes.extend(&(proposer as u64).to_be_bytes());
+                bytes.extend(payload);
+                bytes
+            }
+            Message::Instance { instance, message } => {
+                let mut bytes = vec![4];
+                bytes.extend(&instance.to_be_bytes());
+                bytes.extend(Vec::from(*message));
+                bytes
+            }
+            Message::Decided {
+                instance,
+                phase,
+                value,
+            } => {
+                let mut bytes = vec![5];
+                bytes.extend(&instance.to_be_bytes());
+                bytes.extend(&phase.0.to_be_bytes());
+                bytes.push(value.into());
+                bytes
+            }
         }
     }
 }
@@ -40,45 +114,52 @@ impl TryFrom<Vec<u8>> for Message {
     type Error = &'static str;
 
     fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
-        let num_bytes = bytes.len();
-        match num_bytes {
-            9 => {
-                if bytes[0] != 0 {
-                    Err("not an undecided proposal")
-                } else {
-                    let phase = Phase(u64::from_be_bytes(
-                        bytes[1..9].try_into().expect("array of 8"),
-                    ));
-                    Ok(Message::Proposal { phase, value: None })
-                }
-            }
-            10 => {
-                if bytes[0] == 1 {
-                    let phase = Phase(u64::from_be_bytes(
-                        bytes[1..9].try_into().map_err(|_| "not an array of 8")?,
-                    ));
-                    Ok(Message::Proposal {
-                        phase,
-                        value: Some(bytes[9].try_into()?),
-                    })
-                } else if bytes[0] == 2 {
-                    let phase = Phase(u64::from_be_bytes(
-                        bytes[1..9].try_into().map_err(|_| "not an array of 8")?,
-                    ));
-                    Ok(Message::Report {
-                        phase,
-                        value: bytes[9].try_into()?,
+        let (kind, rest) = bytes.split_first().ok_or("illegal length")?;
+        match (kind, rest.len()) {
+            (0, 8) => Ok(Message::Proposal {
+                phase: Phase(read_u64(rest)),
+                value: None,
+            }),
+            (1, 9) => Ok(Message::Proposal {
+                phase: Phase(read_u64(rest)),
+                value: Some(rest[8].try_into()?),
+            }),
+            (2, 9) => Ok(Message::Report {
+                phase: Phase(read_u64(rest)),
+                value: rest[8].try_into()?,
+            }),
+            (3, len) if len >= 8 => Ok(Message::Candidate {
+                proposer: read_u64(rest)
+                    .try_into()
+                    .map_err(|_| "proposer out of range")?,
+                payload: rest[8..].to_vec(),
+            }),
+            (4, len) if len >= 8 => match Message::try_from(rest[8..].to_vec())? {
+                message @ Message::Proposal { .. } | message @ Message::Report { .. } => {
+                    Ok(Message::Instance {
+                        instance: read_u64(rest),
+                        message: Box::new(message),
                     })
-                } else {
-                    Err("not a decided proposal or report")
                 }
-            }
-            _ => Err("illegal length"),
+                _ => Err("not a report or proposal in an instance"),
+            },
+            (5, 17) => Ok(Message::Decided {
+                instance: read_u64(rest),
+                phase: Phase(read_u64(&rest[8..])),
+                value: rest[16].try_into()?,
+            }),
+            (0..=5, _) => Err("illegal length"),
+            _ => Err("unknown kind of message"),
         }
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+// the big-endian number in the first 8 of at least 8 bytes
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_be_bytes(bytes[..8].try_into().expect("array of 8"))
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, PartialOrd, Serialize)]
 pub struct Phase(pub(crate) u64);
 
 impl Phase {
@@ -95,12 +176,13 @@ fn count_from(init: u64) -> impl Iterator<Item = u64> {
     let mut current = init;
     iter::repeat_with(move || {
         let temp = current;
-        current = current + 1;
+        current += 1;
         temp
     })
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(try_from = "u8", into = "u8")]
 pub enum Value {
     One,
     Zero,
@@ -236,4 +318,48 @@ mod tests {
         assert_eq!(report_bytes, Vec::<u8>::from(report.clone()));
         assert_eq!(Ok(report), Message::try_from(report_bytes));
     }
+
+    #[test]
+    fn multi_valued_serialization() {
+        let candidate = Message::Candidate {
+            proposer: 2,
+            payload: b"hi".to_vec(),
+        };
+        let candidate_bytes: Vec<u8> = vec![3, 0, 0, 0, 0, 0, 0, 0, 2, b'h', b'i'];
+        assert_eq!(candidate_bytes, Vec::<u8>::from(candidate.clone()));
+        assert_eq!(Ok(candidate), Message::try_from(candidate_bytes));
+
+        let instance = Message::Instance {
+            instance: 1,
+            message: Box::new(Message::Report {
+                phase: Phase(3),
+                value: Value::One,
+            }),
+        };
+        let instance_bytes: Vec<u8> = vec![4, 0, 0, 0, 0, 0, 0, 0, 1, 2, 0, 0, 0, 0, 0, 0, 0, 3, 1];
+        assert_eq!(instance_bytes, Vec::<u8>::from(instance.clone()));
+        assert_eq!(Ok(instance), Message::try_from(instance_bytes));
+
+        let decided = Message::Decided {
+            instance: 1,
+            phase: Phase(3),
+            value: Value::Zero,
+        };
+        assert_eq!(
+            Ok(decided.clone()),
+            Message::try_from(Vec::<u8>::from(decided))
+        );
+
+        // instances don't nest
+        let nested = Message::Instance {
+            instance: 0,
+            message: Box::new(Message::Candidate {
+                proposer: 0,
+                payload: vec![],
+            }),
+        };
+        assert!(Message::try_from(Vec::<u8>::from(nested)).is_err());
+        assert!(Message::try_from(vec![]).is_err());
+        assert!(Message::try_from(vec![6, 0]).is_err());
+    }
 }
@@ -0,0 +1,162 @@
+use crate::{
+    message::Value,
+    outcome::Outcome,
+    process::{Id, ProcessConfig},
+    step::Behavior,
+    trace::{Event, TraceEvent},
+};
+
+/// Where every process of a traced run is at, as far as its trace and
+/// outcomes tell.
+pub struct Monitor {
+    processes: Vec<ProcessState>,
+}
+
+/// Where a process is at.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcessState {
+    pub behavior: Behavior,
+    /// The phase the process is in.
+    pub phase: Option<u64>,
+    /// The value the process reported in its phase.
+    pub value: Option<Value>,
+    /// The kind of message the process waits for, and from how many of the
+    /// processes it needs it has heard so far.
+    pub waiting: Option<(char, usize, usize)>,
+    /// The phase in which the process first decided, and what.
+    pub decided: Option<(u64, Value)>,
+    /// Why the process can't go on, if it can't.
+    pub halted: Option<String>,
+}
+
+impl Monitor {
+    pub fn new(processes: &[ProcessConfig]) -> Self {
+        Monitor {
+            processes: processes
+                .iter()
+                .map(|config| ProcessState {
+                    behavior: config.behavior.clone(),
+                    phase: None,
+                    value: None,
+                    waiting: None,
+                    decided: None,
+                    halted: None,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn processes(&self) -> &[ProcessState] {
+        &self.processes
+    }
+
+    pub fn record(&mut self, traced: &TraceEvent) {
+        let state = match self.processes.get_mut(traced.process) {
+            Some(state) => state,
+            None => return,
+        };
+        match &traced.event {
+            // a report opens a phase, with the value the process goes in with
+            Event::Send {
+                kind: 'R',
+                value: Some(value),
+                ..
+            } if state.phase.is_none_or(|phase| phase < traced.phase) => {
+                state.phase = Some(traced.phase);
+                state.value = Some(value.clone());
+            }
+            Event::Wait { kind, have, need } if have < need => {
+                state.waiting = Some((*kind, *have, *need));
+            }
+            Event::Wait { .. } => state.waiting = None,
+            Event::Decide { value } if state.decided.is_none() => {
+                state.decided = Some((traced.phase, value.clone()));
+            }
+            _ => {}
+        }
+    }
+
+    pub fn observe(&mut self, id: &Id, outcome: &Outcome) {
+        let state = match self.processes.get_mut(id.0) {
+            Some(state) => state,
+            None => return,
+        };
+        if let Some(e) = outcome.failure() {
+            state.halted = Some(format!("failed: {}", e));
+        } else if let Some(stall) = outcome.stall() {
+            state.halted = Some(format!("stalled {}", stall));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        message::{Message, Phase},
+        outcome::{Decision, Stall},
+    };
+
+    #[test]
+    fn follows_a_process_through_a_phase() {
+        let mut monitor = Monitor::new(&[ProcessConfig {
+            init: Value::One,
+            behavior: Behavior::Correct,
+        }]);
+        let traced = |phase, event| TraceEvent {
+            process: 0,
+            phase,
+            event,
+        };
+        let report = Message::Report {
+            phase: Phase(1),
+            value: Value::One,
+        };
+        for event in [
+            traced(1, Event::send(0, &report).1),
+            traced(
+                1,
+                Event::Wait {
+                    kind: 'R',
+                    have: 1,
+                    need: 2,
+                },
+            ),
+        ] {
+            monitor.record(&event);
+        }
+        let state = &monitor.processes()[0];
+        assert_eq!(state.phase, Some(1));
+        assert_eq!(state.value, Some(Value::One));
+        assert_eq!(state.waiting, Some(('R', 1, 2)));
+
+        monitor.record(&traced(
+            1,
+            Event::Wait {
+                kind: 'R',
+                have: 2,
+                need: 2,
+            },
+        ));
+        monitor.record(&traced(1, Event::Decide { value: Value::One }));
+        monitor.observe(
+            &Id(0),
+            &Outcome {
+                phase: Phase(2),
+                decision: Decision::Stalled(Stall {
+                    waiting_for: 'P',
+                    have: 0,
+                    need: 2,
+                }),
+            },
+        );
+        let state = &monitor.processes()[0];
+        assert_eq!(state.waiting, None);
+        assert_eq!(state.decided, Some((1, Value::One)));
+        assert_eq!(
+            state.halted.as_deref(),
+            Some("stalled waiting for P messages, have 0 of 2")
+        );
+    }
+}
@@ -0,0 +1,502 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    error, fmt,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use tracing::{info, info_span};
+
+use crate::{
+    coin::CoinType,
+    message::{Message, Phase, Value},
+    outcome::{block_on, Context, Decision, ProcessId, StepFn},
+    step::{Behavior, Protocol},
+    trace::Recorder,
+    transport::{Stopper, Transport, TransportError, MAX_FRAME_LEN},
+};
+
+// a candidate is encoded as a tag and its proposer before the payload
+const CANDIDATE_HEADER_LEN: usize = 9;
+
+/// The largest payload `agree` takes, so that its candidate fits in a frame
+/// of a `TcpTransport`, whatever transport it runs on.
+pub const MAX_PAYLOAD_LEN: usize = MAX_FRAME_LEN as usize - CANDIDATE_HEADER_LEN;
+
+/// What a process of multi-valued consensus decided.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Agreement {
+    /// The process whose payload was decided.
+    pub proposer: usize,
+    pub payload: Vec<u8>,
+    /// How many binary instances it took to decide.
+    pub instances: u64,
+}
+
+/// Why a process of multi-valued consensus didn't decide.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AgreementError {
+    Transport(TransportError),
+    /// The payload, of this many bytes, is longer than `MAX_PAYLOAD_LEN`.
+    TooLarge(usize),
+    /// The thread the process ran on panicked.
+    Panicked,
+}
+
+impl fmt::Display for AgreementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgreementError::Transport(e) => write!(f, "{}", e),
+            AgreementError::TooLarge(len) => write!(
+                f,
+                "a payload of {} bytes is longer than {}",
+                len, MAX_PAYLOAD_LEN
+            ),
+            AgreementError::Panicked => write!(f, "the process panicked"),
+        }
+    }
+}
+
+impl error::Error for AgreementError {}
+
+impl From<TransportError> for AgreementError {
+    fn from(e: TransportError) -> Self {
+        AgreementError::Transport(e)
+    }
+}
+
+/// Runs multi-valued consensus as process `id`, proposing `payload`, and
+/// returns the payload the processes agree on, which is one of theirs.
+///
+/// Every process first broadcasts its payload as a candidate and waits for
+/// the candidates of n - f processes. Binary instances of the algorithm then
+/// decide, for one proposer after the other, whether to adopt its candidate:
+/// a process votes 1 if it has the candidate, after relaying it so that every
+/// process gets it, and 0 otherwise. The first candidate decided 1 is the
+/// decision. This tolerates up to `num_adversaries` crashed processes, as
+/// long as they're fewer than half of them.
+///
+/// A payload longer than `MAX_PAYLOAD_LEN` is rejected before anything is
+/// sent.
+pub fn agree(
+    id: usize,
+    payload: Vec<u8>,
+    num_adversaries: usize,
+    transport: Box<dyn Transport>,
+    seed: u64,
+) -> Result<Agreement, AgreementError> {
+    let num_processes = transport.num_senders();
+    assert!(id < num_processes);
+    // with fewer than half crashed, an instance in which nobody has the
+    // candidate decides 0 in its first phase, before any coin is flipped
+    assert!(num_processes > 2 * num_adversaries);
+    if payload.len() > MAX_PAYLOAD_LEN {
+        return Err(AgreementError::TooLarge(payload.len()));
+    }
+    transport.send(Message::Candidate {
+        proposer: id,
+        payload,
+    })?;
+    let layer = Arc::new(Mutex::new(Layer::new(transport)));
+    layer
+        .lock()
+        .expect("lock")
+        .wait_for_candidates(num_processes - num_adversaries)?;
+
+    let mut rng = crate::process_rng(seed, id);
+    let step_fn = Behavior::Correct.step_fn(&mut rng);
+    let mut coin = CoinType::Local.coin(seed);
+    let mut instance = 0;
+    loop {
+        let _span = info_span!("instance", process = id, instance).entered();
+        let proposer = (instance % num_processes as u64) as usize;
+        let vote = {
+            let mut layer = layer.lock().expect("lock");
+            layer.start(instance);
+            match layer.candidates.get(&proposer).cloned() {
+                Some(payload) => {
+                    // relayed before voting for it, so that every process
+                    // gets the candidate if it's decided, even if its
+                    // proposer crashed while sending it
+                    layer
+                        .transport
+                        .send(Message::Candidate { proposer, payload })?;
+                    Value::One
+                }
+                None => Value::Zero,
+            }
+        };
+        let mut context = Context {
+            id: ProcessId(id),
+            transport: Box::new(InstanceTransport(layer.clone())),
+            rng,
+            deferred: vec![],
+            stall_timeout: None,
+            protocol: Protocol::BenOr,
+            coin,
+            recorder: Recorder::off(id),
+        };
+        let decided = decide(&mut context, step_fn, vote, num_adversaries);
+        rng = context.rng;
+        coin = context.coin;
+        let (phase, value) = decided?;
+        info!(proposer, adopt = %value, "decided whether to adopt the candidate");
+
+        let mut layer = layer.lock().expect("lock");
+        layer.transport.send(Message::Decided {
+            instance,
+            phase,
+            value: value.clone(),
+        })?;
+        if value == Value::One {
+            let payload = layer.wait_for_candidate(proposer)?;
+            return Ok(Agreement {
+                proposer,
+                payload,
+                instances: instance + 1,
+            });
+        }
+        instance += 1;
+    }
+}
+
+/// Runs `agree` for every process on its own thread, process `i` proposing
+/// `payloads[i]` over `transports[i]`, and returns what each of them decided.
+pub fn simulate_agreement(
+    payloads: Vec<Vec<u8>>,
+    num_adversaries: usize,
+    transports: Vec<Box<dyn Transport>>,
+    seed: u64,
+) -> Vec<Result<Agreement, AgreementError>> {
+    assert_eq!(payloads.len(), transports.len());
+    let threads = payloads
+        .into_iter()
+        .zip(transports)
+        .enumerate()
+        .map(|(id, (payload, transport))| {
+            thread::spawn(move || agree(id, payload, num_adversaries, transport, seed))
+        })
+        .collect::<Vec<_>>();
+    threads
+        .into_iter()
+        .map(|thread| thread.join().unwrap_or(Err(AgreementError::Panicked)))
+        .collect()
+}
+
+// runs a binary instance until the process decides, returning the phase and
+// the value
+fn decide(
+    context: &mut Context,
+    step_fn: StepFn,
+    vote: Value,
+    num_adversaries: usize,
+) -> Result<(Phase, Value), TransportError> {
+    let mut next = vote;
+    for phase in Phase::generate().skip(1) {
+        next = match block_on(step_fn(context, phase, next, num_adversaries))? {
+            Decision::Done { decided, .. } => return Ok((phase, decided)),
+            Decision::Pending { next } => next,
+            Decision::Failed(e) => return Err(e),
+            Decision::Stalled(stall) => unreachable!("no timeout, but {}", stall),
+        }
+    }
+    unreachable!("phases never run out")
+}
+
+// what a process of multi-valued consensus knows between the binary
+// instances, shared with the transport the current instance runs on
+struct Layer {
+    transport: Box<dyn Transport>,
+    instance: u64,
+    candidates: BTreeMap<usize, Vec<u8>>,
+    // messages of the current instance not received through the transport
+    // yet
+    ready: VecDeque<(usize, Message)>,
+    // messages of later instances
+    later: Vec<(usize, Message)>,
+    // the processes that decided the current instance, in which phase and
+    // what
+    decided: HashMap<usize, (Phase, Value)>,
+    // the latest phases this process reported and proposed in
+    reported: Option<Phase>,
+    proposed: Option<Phase>,
+}
+
+impl Layer {
+    fn new(transport: Box<dyn Transport>) -> Self {
+        Layer {
+            transport,
+            instance: 0,
+            candidates: BTreeMap::new(),
+            ready: VecDeque::new(),
+            later: vec![],
+            decided: HashMap::new(),
+            reported: None,
+            proposed: None,
+        }
+    }
+
+    fn wait_for_candidates(&mut self, num_candidates: usize) -> Result<(), TransportError> {
+        while self.candidates.len() < num_candidates {
+            match self.transport.receive()? {
+                (from, message @ Message::Candidate { .. }) => {
+                    self.accept(from, message);
+                }
+                // no instance has started, so its messages wait for it
+                received => self.later.push(received),
+            }
+        }
+        Ok(())
+    }
+
+    fn wait_for_candidate(&mut self, proposer: usize) -> Result<Vec<u8>, TransportError> {
+        loop {
+            if let Some(payload) = self.candidates.get(&proposer) {
+                return Ok(payload.clone());
+            }
+            let (from, message) = self.transport.receive()?;
+            self.accept(from, message);
+        }
+    }
+
+    // moves on to the given instance, with the messages that arrived early
+    // for it
+    fn start(&mut self, instance: u64) {
+        self.instance = instance;
+        self.ready.clear();
+        self.decided.clear();
+        self.reported = None;
+        self.proposed = None;
+        for (from, message) in std::mem::take(&mut self.later) {
+            if let Some(received) = self.accept(from, message) {
+                self.ready.push_back(received);
+            }
+        }
+    }
+
+    // keeps what a message tells about the candidates or later instances,
+    // and returns it unwrapped if it belongs to the current instance
+    fn accept(&mut self, from: usize, message: Message) -> Option<(usize, Message)> {
+        match message {
+            Message::Candidate { proposer, payload } => {
+                self.candidates.entry(proposer).or_insert(payload);
+                None
+            }
+            Message::Instance { instance, message } if instance == self.instance => {
+                Some((from, *message))
+            }
+            Message::Decided {
+                instance,
+                phase,
+                value,
+            } if instance == self.instance => {
+                self.stand_in(from, phase, &value, self.reported, self.proposed);
+                self.decided.insert(from, (phase, value));
+                None
+            }
+            Message::Instance { instance, .. } | Message::Decided { instance, .. }
+                if instance < self.instance =>
+            {
+                None
+            }
+            message @ Message::Instance { .. } | message @ Message::Decided { .. } => {
+                self.later.push((from, message));
+                None
+            }
+            Message::Report { .. } | Message::Proposal { .. } => None,
+        }
+    }
+
+    // a process that decided would go on reporting and proposing its value,
+    // so its messages for the phases after it decided are made up for it
+    fn stand_in(
+        &mut self,
+        from: usize,
+        decided: Phase,
+        value: &Value,
+        reported: Option<Phase>,
+        proposed: Option<Phase>,
+    ) {
+        if let Some(phase) = reported.filter(|phase| *phase > decided) {
+            let report = Message::Report {
+                phase,
+                value: value.clone(),
+            };
+            self.ready.push_back((from, report));
+        }
+        if let Some(phase) = proposed.filter(|phase| *phase > decided) {
+            let proposal = Message::Proposal {
+                phase,
+                value: Some(value.clone()),
+            };
+            self.ready.push_back((from, proposal));
+        }
+    }
+
+    // notes a report or proposal this process broadcasts, and makes up the
+    // ones of the processes that decided already
+    fn sent(&mut self, message: &Message) {
+        let (reported, proposed) = match message {
+            Message::Report { phase, .. } => (Some(*phase), None),
+            Message::Proposal { phase, .. } => (None, Some(*phase)),
+            _ => (None, None),
+        };
+        self.reported = reported.or(self.reported);
+        self.proposed = proposed.or(self.proposed);
+        let decided = self
+            .decided
+            .iter()
+            .map(|(from, (phase, value))| (*from, *phase, value.clone()))
+            .collect::<Vec<_>>();
+        for (from, phase, value) in decided {
+            self.stand_in(from, phase, &value, reported, proposed);
+        }
+    }
+
+    fn receive(&mut self, wait: bool) -> Result<Option<(usize, Message)>, TransportError> {
+        loop {
+            if let Some(received) = self.ready.pop_front() {
+                return Ok(Some(received));
+            }
+            let (from, message) = if wait {
+                self.transport.receive()?
+            } else {
+                match self.transport.try_receive()? {
+                    Some(received) => received,
+                    None => return Ok(None),
+                }
+            };
+            if let Some(received) = self.accept(from, message) {
+                return Ok(Some(received));
+            }
+        }
+    }
+}
+
+// the transport of the current binary instance, which tags what the
+// algorithm sends with the instance and only hands it the instance's messages
+struct InstanceTransport(Arc<Mutex<Layer>>);
+
+impl InstanceTransport {
+    fn wrap(layer: &Layer, message: Message) -> Message {
+        Message::Instance {
+            instance: layer.instance,
+            message: Box::new(message),
+        }
+    }
+}
+
+impl Transport for InstanceTransport {
+    fn num_senders(&self) -> usize {
+        self.0.lock().expect("lock").transport.num_senders()
+    }
+
+    fn send(&self, message: Message) -> Result<(), TransportError> {
+        let mut layer = self.0.lock().expect("lock");
+        layer.sent(&message);
+        let message = Self::wrap(&layer, message);
+        layer.transport.send(message)
+    }
+
+    fn send_to(&self, to: usize, message: Message) -> Result<(), TransportError> {
+        let layer = self.0.lock().expect("lock");
+        layer.transport.send_to(to, Self::wrap(&layer, message))
+    }
+
+    fn send_to_self(&self, message: Message) -> Result<(), TransportError> {
+        let layer = self.0.lock().expect("lock");
+        layer.transport.send_to_self(Self::wrap(&layer, message))
+    }
+
+    fn receive(&self) -> Result<(usize, Message), TransportError> {
+        let received = self.0.lock().expect("lock").receive(true)?;
+        Ok(received.expect("waited for a message"))
+    }
+
+    fn try_receive(&self) -> Result<Option<(usize, Message)>, TransportError> {
+        self.0.lock().expect("lock").receive(false)
+    }
+    fn stopper(&self) -> Stopper {
+        self.0.lock().expect("lock").transport.stopper()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::transport::{MessageChannel, TcpTransport};
+
+    fn payloads(num_processes: usize) -> Vec<Vec<u8>> {
+        (0..num_processes)
+            .map(|id| format!("payload {}", id).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn processes_agree_on_one_of_their_payloads() {
+        for seed in 0..10 {
+            let agreements = simulate_agreement(payloads(5), 2, MessageChannel::new(5), seed)
+                .into_iter()
+                .map(|agreement| agreement.expect("agree"))
+                .collect::<Vec<_>>();
+            assert!(agreements
+                .iter()
+                .all(|a| a.payload == agreements[0].payload));
+            assert_eq!(agreements[0].payload, payloads(5)[agreements[0].proposer]);
+        }
+    }
+
+    #[test]
+    fn payloads_fit_in_a_frame() {
+        let candidate = Message::Candidate {
+            proposer: 0,
+            payload: vec![0; MAX_PAYLOAD_LEN],
+        };
+        assert_eq!(Vec::<u8>::from(candidate).len(), MAX_FRAME_LEN as usize);
+    }
+
+    #[test]
+    fn longest_payloads_are_agreed_on_over_tcp() {
+        let payloads = (0..3)
+            .map(|id| vec![id as u8; MAX_PAYLOAD_LEN])
+            .collect::<Vec<_>>();
+        let transports = TcpTransport::local(3).expect("local");
+        let agreements = simulate_agreement(payloads.clone(), 1, transports, 0)
+            .into_iter()
+            .map(|agreement| agreement.expect("agree"))
+            .collect::<Vec<_>>();
+        assert!(agreements.iter().all(|a| a == &agreements[0]));
+        assert_eq!(agreements[0].payload, payloads[agreements[0].proposer]);
+    }
+
+    #[test]
+    fn longer_payloads_are_rejected() {
+        let transport = MessageChannel::new(1).remove(0);
+        assert_eq!(
+            agree(0, vec![0; MAX_PAYLOAD_LEN + 1], 0, transport, 0),
+            Err(AgreementError::TooLarge(MAX_PAYLOAD_LEN + 1))
+        );
+    }
+
+    #[test]
+    fn tolerates_a_process_that_crashed_before_proposing() {
+        let mut transports = MessageChannel::new(4);
+        drop(transports.remove(0));
+        let threads = transports
+            .into_iter()
+            .enumerate()
+            .map(|(i, transport)| {
+                let id = i + 1;
+                thread::spawn(move || agree(id, vec![id as u8], 1, transport, 3))
+            })
+            .collect::<Vec<_>>();
+        let agreements = threads
+            .into_iter()
+            .map(|thread| thread.join().expect("join").expect("agree"))
+            .collect::<Vec<_>>();
+        assert_ne!(agreements[0].proposer, 0);
+        assert_eq!(agreements[0].payload, vec![agreements[0].proposer as u8]);
+        assert!(agreements.iter().all(|a| a == &agreements[0]));
+    }
+}
@@ -1,55 +1,153 @@
-use std::fmt;
+use std::{
+    fmt,
+    future::Future,
+    pin::{pin, Pin},
+    task::{self, Poll, Waker},
+    time::Duration,
+};
+
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+use tracing::{info_span, Instrument};
 
 use crate::{
-    message::{Phase, Value},
-    transport::Transport,
+    coin::Coin,
+    message::{Message, Phase, Value},
+    step::Protocol,
+    trace::Recorder,
+    transport::{Transport, TransportError},
 };
 
 pub(crate) struct Context {
     pub(crate) id: ProcessId,
     pub(crate) transport: Box<dyn Transport>,
+    pub(crate) rng: StdRng,
+    // messages for later phases that arrived early, with their senders
+    pub(crate) deferred: Vec<(usize, Message)>,
+    // how long a wait goes without counting a new sender before it gives up
+    pub(crate) stall_timeout: Option<Duration>,
+    pub(crate) protocol: Protocol,
+    pub(crate) coin: Box<dyn Coin>,
+    pub(crate) recorder: Recorder,
 }
 
 #[derive(Clone)]
 pub(crate) struct ProcessId(pub(crate) usize);
 
-#[derive(Debug, PartialEq)]
+/// What a process got to in a phase. It serializes so that a process can
+/// report it to another, e.g. a node to the `cluster` that launched it.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
 pub struct Outcome {
-    phase: Phase,
-    decision: Decision,
+    pub(crate) phase: Phase,
+    pub(crate) decision: Decision,
 }
 
 impl Outcome {
-    pub(crate) fn generate(
+    pub(crate) fn generate<P: Iterator<Item = Phase>>(
         init: Value,
-        phases: impl Iterator<Item = Phase>,
-        step_fn: impl Fn(&Context, Phase, Value, usize) -> Decision,
+        phases: P,
+        step_fn: StepFn,
         context: Context,
         num_adversaries: usize,
-    ) -> impl Iterator<Item = Self> {
-        let mut current = Decision::Pending { next: init };
-        phases.map(move |phase| {
-            let temp = current.clone();
-            current = match current.clone() {
-                Decision::Done {
-                    next,
-                    decided: prev_decided,
-                } => {
-                    let decision = step_fn(&context, phase.next(), next, num_adversaries);
-                    if let Some(decided) = decision.decided() {
-                        assert_eq!(prev_decided, decided);
-                    }
-                    decision
-                }
-                Decision::Pending { next } => {
-                    step_fn(&context, phase.next(), next, num_adversaries)
+    ) -> Outcomes<P> {
+        Outcomes {
+            phases,
+            current: Some(Decision::Pending { next: init }),
+            step_fn,
+            context,
+            num_adversaries,
+        }
+    }
+}
+
+/// One phase of a process: the algorithm, or whatever the process does
+/// instead. It waits for messages as a future, so that a scheduler can
+/// drive the process as well as a thread.
+pub(crate) type Step<'a> = Pin<Box<dyn Future<Output = Result<Decision, TransportError>> + 'a>>;
+
+pub(crate) type StepFn = for<'a> fn(&'a mut Context, Phase, Value, usize) -> Step<'a>;
+
+/// The outcomes of a process, one per phase. They end with the first failed
+/// or stalled one.
+pub(crate) struct Outcomes<P> {
+    phases: P,
+    current: Option<Decision>,
+    step_fn: StepFn,
+    context: Context,
+    num_adversaries: usize,
+}
+
+impl<P: Iterator<Item = Phase>> Outcomes<P> {
+    pub(crate) async fn next_outcome(&mut self) -> Option<Outcome> {
+        let phase = self.phases.next()?;
+        let decision = self.current.take()?;
+        self.current = match decision.clone() {
+            Decision::Done {
+                next,
+                decided: prev_decided,
+            } => {
+                let decision = self.step(phase.next(), next).await;
+                if let Some(decided) = decision.decided() {
+                    assert_eq!(prev_decided, decided);
                 }
-            };
-            Outcome {
-                phase,
-                decision: temp,
+                Some(decision)
             }
-        })
+            Decision::Pending { next } => Some(self.step(phase.next(), next).await),
+            Decision::Failed(_) | Decision::Stalled(_) => None,
+        };
+        Some(Outcome { phase, decision })
+    }
+
+    async fn step(&mut self, phase: Phase, value: Value) -> Decision {
+        let span = info_span!("phase", process = self.context.id.0, phase = phase.0);
+        (self.step_fn)(&mut self.context, phase, value, self.num_adversaries)
+            .instrument(span)
+            .await
+            .unwrap_or_else(Decision::Failed)
+    }
+}
+
+impl<P: Iterator<Item = Phase>> Iterator for Outcomes<P> {
+    type Item = Outcome;
+
+    fn next(&mut self) -> Option<Outcome> {
+        block_on(self.next_outcome())
+    }
+}
+
+// runs a step on the calling thread, whose transport blocks rather than
+// returning `Poll::Pending`, so that it's done after a single poll
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let mut cx = task::Context::from_waker(Waker::noop());
+    match pin!(future).poll(&mut cx) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("a blocking transport returned Poll::Pending"),
+    }
+}
+
+impl Outcome {
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    pub fn decided(&self) -> Option<Value> {
+        self.decision.decided()
+    }
+
+    /// Why the process couldn't go on, if it couldn't.
+    pub fn failure(&self) -> Option<&TransportError> {
+        match &self.decision {
+            Decision::Failed(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// What the process was waiting for when it stalled, if it did.
+    pub fn stall(&self) -> Option<&Stall> {
+        match &self.decision {
+            Decision::Stalled(stall) => Some(stall),
+            _ => None,
+        }
     }
 }
 
@@ -63,21 +161,47 @@ impl fmt::Display for Outcome {
                 phase.0, next, decided
             ),
             Decision::Pending { next } => write!(f, "(Phase: {}, Next: {})", phase.0, next),
+            Decision::Failed(e) => write!(f, "(Phase: {}, Failed: {})", phase.0, e),
+            Decision::Stalled(stall) => write!(f, "(Phase: {}, Stalled {})", phase.0, stall),
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// A wait that ran out of time before enough processes were heard from.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Stall {
+    /// The kind of message waited for, `'R'` or `'P'`.
+    pub waiting_for: char,
+    /// How many distinct processes had sent one.
+    pub have: usize,
+    /// How many were needed.
+    pub need: usize,
+}
+
+impl fmt::Display for Stall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "waiting for {} messages, have {} of {}",
+            self.waiting_for, self.have, self.need
+        )
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub(crate) enum Decision {
     Done { next: Value, decided: Value },
     Pending { next: Value },
+    Failed(TransportError),
+    Stalled(Stall),
 }
 
 impl Decision {
     fn decided(&self) -> Option<Value> {
         match &self {
             Decision::Done { next: _, decided } => Some(decided.clone()),
-            Decision::Pending { next: _ } => None,
+            Decision::Pending { next: _ } | Decision::Failed(_) | Decision::Stalled(_) => None,
         }
     }
 }
@@ -86,37 +210,45 @@ impl Decision {
 mod tests {
     use super::*;
 
-    use crate::transport::MessageChannel;
+    use rand::SeedableRng;
+
+    use crate::{coin::LocalCoin, step::Behavior, transport::MessageChannel};
 
-    fn step_fn(_context: &Context, phase: Phase, _value: Value, _: usize) -> Decision {
+    fn step_fn(_context: &mut Context, phase: Phase, _value: Value, _: usize) -> Step<'_> {
         let next = if phase.0 % 2 == 1 {
             Value::Zero
         } else {
             Value::One
         };
-        if phase.0 >= 4 {
-            Decision::Done {
-                next,
-                decided: Value::Zero,
+        Box::pin(async move {
+            if phase.0 >= 4 {
+                Ok(Decision::Done {
+                    next,
+                    decided: Value::Zero,
+                })
+            } else {
+                Ok(Decision::Pending { next })
             }
-        } else {
-            Decision::Pending { next }
+        })
+    }
+
+    fn context() -> Context {
+        Context {
+            id: ProcessId(0),
+            transport: MessageChannel::new(1).remove(0),
+            rng: StdRng::seed_from_u64(0),
+            deferred: vec![],
+            stall_timeout: None,
+            protocol: Protocol::BenOr,
+            coin: Box::new(LocalCoin),
+            recorder: Recorder::off(0),
         }
     }
 
     #[test]
     fn outcome_generate_works() {
-        let mut it = Outcome::generate(
-            Value::Zero,
-            Phase::generate(),
-            step_fn,
-            Context {
-                id: ProcessId(0),
-                transport: MessageChannel::new(1).remove(0),
-            },
-            0,
-        )
-        .take(6);
+        let mut it =
+            Outcome::generate(Value::Zero, Phase::generate(), step_fn, context(), 0).take(6);
         assert_eq!(
             it.next(),
             Some(Outcome {
@@ -167,4 +299,52 @@ mod tests {
         );
         assert_eq!(it.next(), None);
     }
+
+    #[test]
+    fn outcome_generate_ends_with_a_failure() {
+        fn step_fn(_: &mut Context, phase: Phase, value: Value, _: usize) -> Step<'_> {
+            Box::pin(async move {
+                if phase.0 >= 2 {
+                    Err(TransportError::Disconnected)
+                } else {
+                    Ok(Decision::Pending { next: value })
+                }
+            })
+        }
+        let outcomes = Outcome::generate(Value::One, Phase::generate(), step_fn, context(), 0)
+            .collect::<Vec<_>>();
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(outcomes[1].failure(), None);
+        assert_eq!(outcomes[2].failure(), Some(&TransportError::Disconnected));
+        assert_eq!(outcomes[2].to_string(), "(Phase: 2, Failed: disconnected)");
+    }
+
+    #[test]
+    fn outcome_generate_is_deterministic_for_seed() {
+        let run = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let step_fn = Behavior::SendsInvalidMessages.step_fn(&mut rng);
+            Outcome::generate(
+                Value::Zero,
+                Phase::generate(),
+                step_fn,
+                Context {
+                    id: ProcessId(0),
+                    transport: MessageChannel::new(1).remove(0),
+                    rng,
+                    deferred: vec![],
+                    stall_timeout: None,
+                    protocol: Protocol::BenOr,
+                    coin: Box::new(LocalCoin),
+                    recorder: Recorder::off(0),
+                },
+                0,
+            )
+            .take(20)
+            .collect::<Vec<_>>()
+        };
+        for seed in 0..8 {
+            assert_eq!(run(seed), run(seed));
+        }
+    }
 }
@@ -1,17 +1,72 @@
-use std::fmt;
+use std::{fmt, time::Duration};
+
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    coin::Coin,
     message::{Phase, Value},
-    outcome::{self, Context, Decision, Outcome},
+    outcome::{self, Context, Outcome, Outcomes, StepFn},
+    step::{Behavior, Protocol},
+    trace::Recorder,
     transport::Transport,
 };
 
+/// How a process starts and how it behaves.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ProcessConfig {
+    pub init: Value,
+    #[serde(default = "correct")]
+    pub behavior: Behavior,
+}
+
+fn correct() -> Behavior {
+    Behavior::Correct
+}
+
+impl ProcessConfig {
+    /// The processes that `simulate` sets up: the first `num_zeros` start
+    /// with 0, and the first `num_adversaries` behave as `adversarial_behavior`.
+    pub fn for_simulation(
+        num_processes: usize,
+        num_zeros: usize,
+        num_adversaries: usize,
+        adversarial_behavior: &Behavior,
+    ) -> Vec<Self> {
+        assert!(num_zeros <= num_processes);
+        (0..num_processes)
+            .map(|i| ProcessConfig {
+                init: if i < num_zeros {
+                    Value::Zero
+                } else {
+                    Value::One
+                },
+                behavior: if i < num_adversaries {
+                    adversarial_behavior.clone()
+                } else {
+                    Behavior::Correct
+                },
+            })
+            .collect()
+    }
+
+    /// Whether the process follows the algorithm.
+    pub fn is_correct(&self) -> bool {
+        self.behavior == Behavior::Correct
+    }
+}
+
 pub(crate) struct Process {
     pub(crate) id: Id,
     pub(crate) transport: Box<dyn Transport>,
+    pub(crate) rng: StdRng,
+    pub(crate) stall_timeout: Option<Duration>,
+    pub(crate) protocol: Protocol,
+    pub(crate) coin: Box<dyn Coin>,
+    pub(crate) recorder: Recorder,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct Id(pub(crate) usize);
 
 impl fmt::Display for Id {
@@ -24,10 +79,18 @@ impl Process {
     pub(crate) fn run(
         self,
         init: Value,
-        step_fn: impl Fn(&Context, Phase, Value, usize) -> Decision,
+        step_fn: StepFn,
         num_adversaries: usize,
-    ) -> impl Iterator<Item = (Id, Outcome)> {
-        let Self { id, transport } = self;
+    ) -> Outcomes<impl Iterator<Item = Phase>> {
+        let Self {
+            id,
+            transport,
+            rng,
+            stall_timeout,
+            protocol,
+            coin,
+            recorder,
+        } = self;
         Outcome::generate(
             init,
             Phase::generate(),
@@ -35,9 +98,14 @@ impl Process {
             Context {
                 id: outcome::ProcessId(id.0),
                 transport,
+                rng,
+                deferred: vec![],
+                stall_timeout,
+                protocol,
+                coin,
+                recorder,
             },
             num_adversaries,
         )
-        .map(move |outcome| (id.clone(), outcome))
     }
 }
@@ -0,0 +1,212 @@
+use std::{
+    sync::mpsc::Receiver,
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    outcome::Outcome,
+    process::{Id, ProcessConfig},
+    step::Algorithm,
+    termination::{Summary, Termination, Tracker},
+    trace::{Recorder, Trace},
+    transport::{Stopper, Transport},
+};
+
+/// A run of `simulate`, with every process on its own thread.
+///
+/// Iterating yields the outcomes as the processes report them, until the
+/// termination policy stops the run. Dropping the run stops the processes
+/// that are still running and waits for their threads.
+pub struct Run {
+    receiver: Receiver<Report>,
+    threads: Vec<JoinHandle<()>>,
+    stoppers: Vec<Stopper>,
+    tracker: Tracker,
+}
+
+impl Run {
+    pub(crate) fn spawn(
+        processes: Vec<ProcessConfig>,
+        num_adversaries: usize,
+        algorithm: Algorithm,
+        transports: Vec<Box<dyn Transport>>,
+        seed: u64,
+        termination: Termination,
+        trace: Option<&Trace>,
+    ) -> Self {
+        assert_eq!(processes.len(), transports.len());
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let correct = processes.iter().map(ProcessConfig::is_correct).collect();
+        let mut threads = vec![];
+        let mut stoppers = vec![];
+        for (i, (config, transport)) in processes.into_iter().zip(transports).enumerate() {
+            let sender = sender.clone();
+            let termination = termination.clone();
+            stoppers.push(transport.stopper());
+            let recorder = trace.map_or_else(|| Recorder::off(i), |trace| trace.recorder(i));
+            threads.push(thread::spawn(move || {
+                for (id, outcome) in crate::run_process(
+                    i,
+                    config,
+                    num_adversaries,
+                    algorithm,
+                    transport,
+                    seed,
+                    &termination,
+                    recorder,
+                ) {
+                    if sender.send(Report::Outcome(id, outcome)).is_err() {
+                        return;
+                    }
+                }
+                let _ = sender.send(Report::Stopped(i));
+            }));
+        }
+        Run {
+            receiver,
+            threads,
+            stoppers,
+            tracker: Tracker::new(termination, correct),
+        }
+    }
+
+    /// How far every process got so far.
+    pub fn summary(&self) -> Summary {
+        self.tracker.summary()
+    }
+}
+
+impl Iterator for Run {
+    type Item = (Id, Outcome);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.tracker.done() {
+            match self.receiver.recv().ok()? {
+                Report::Outcome(id, outcome) => {
+                    self.tracker.observe(&id, &outcome);
+                    return Some((id, outcome));
+                }
+                Report::Stopped(id) => self.tracker.stopped(id),
+            }
+        }
+        None
+    }
+}
+
+enum Report {
+    Outcome(Id, Outcome),
+    // the process was stopped by the termination policy
+    Stopped(usize),
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        for stop in &self.stoppers {
+            stop();
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        checker::SafetyChecker, message::Value, step::Behavior, transport::MessageChannel,
+    };
+
+    #[test]
+    fn stops_once_all_correct_decided() {
+        let mut run = crate::simulate_until(
+            2,
+            1,
+            Behavior::StopsExecuting,
+            MessageChannel::new(4),
+            0,
+            Termination {
+                all_correct_decided: true,
+                ..Termination::default()
+            },
+        );
+        assert!(run.by_ref().count() > 0);
+        let summary = run.summary();
+        assert!(summary.all_correct_decided);
+        assert!(summary.decisions[1..].iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn stops_after_max_phase() {
+        let mut run = crate::simulate_until(
+            1,
+            0,
+            Behavior::Correct,
+            MessageChannel::new(3),
+            0,
+            Termination {
+                max_phase: Some(2),
+                ..Termination::default()
+            },
+        );
+        assert!(run.by_ref().all(|(_, outcome)| outcome.phase().0 <= 2));
+        assert_eq!(run.summary().last_phases, vec![Some(crate::Phase(2)); 3]);
+    }
+
+    #[test]
+    fn adversaries_among_zeros_and_ones() {
+        let config = |init, behavior| ProcessConfig { init, behavior };
+        let processes = vec![
+            config(Value::Zero, Behavior::Correct),
+            config(Value::Zero, Behavior::Crashes),
+            config(Value::One, Behavior::Correct),
+            config(Value::One, Behavior::StopsExecuting),
+            config(Value::One, Behavior::Correct),
+            config(Value::Zero, Behavior::Correct),
+            config(Value::One, Behavior::Correct),
+        ];
+        let mut checker = SafetyChecker::for_processes(&processes);
+        let mut run = crate::simulate_processes(
+            processes,
+            2,
+            MessageChannel::new(7),
+            0,
+            Termination {
+                all_correct_decided: true,
+                ..Termination::default()
+            },
+        );
+        let outcomes = run.by_ref().collect::<Vec<_>>();
+        assert_eq!(checker.check(&outcomes), vec![]);
+        let summary = run.summary();
+        assert!(summary.all_correct_decided);
+        assert!([0, 2, 4, 5, 6]
+            .iter()
+            .all(|&id| summary.decisions[id].is_some()));
+    }
+
+    #[test]
+    fn dropping_stops_processes_waiting_forever() {
+        let config = |behavior| ProcessConfig {
+            init: Value::One,
+            behavior,
+        };
+        // one more silent process than tolerated, so the others never get
+        // enough messages
+        let processes = vec![
+            config(Behavior::Correct),
+            config(Behavior::Correct),
+            config(Behavior::StopsExecuting),
+            config(Behavior::StopsExecuting),
+        ];
+        let run = crate::simulate_processes(
+            processes,
+            1,
+            MessageChannel::new(4),
+            0,
+            Termination::default(),
+        );
+        drop(run);
+    }
+}
@@ -0,0 +1,642 @@
+use std::{convert::TryInto, error, fmt, fs, io, path::Path, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    checker::SafetyChecker,
+    coin::CoinType,
+    outcome::Outcome,
+    process::{Id, ProcessConfig},
+    run::Run,
+    simulator::{AdversarialScheduler, FifoScheduler, RandomScheduler, Scheduler, Simulator},
+    step::{Algorithm, Behavior, Protocol},
+    termination::{Summary, Termination},
+    trace::Trace,
+    transport::{
+        ByteChannel, Faults, FaultyTransport, MessageChannel, PartitionSchedule, TcpTransport,
+        Transport,
+    },
+};
+
+/// The network the processes of a run talk over.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportType {
+    MessageChannel,
+    ByteChannel,
+    Tcp,
+    DiscreteFifo,
+    DiscreteRandom,
+    DiscreteAdversarial,
+}
+
+impl TransportType {
+    /// The transports of a threaded run, or `None` for the discrete-event
+    /// simulator.
+    pub fn transports(self, num_processes: usize) -> io::Result<Option<Vec<Box<dyn Transport>>>> {
+        Ok(Some(match self {
+            TransportType::MessageChannel => MessageChannel::new(num_processes),
+            TransportType::ByteChannel => ByteChannel::new(num_processes),
+            TransportType::Tcp => TcpTransport::local(num_processes)?,
+            _ => return Ok(None),
+        }))
+    }
+
+    /// The scheduler of a discrete-event simulation, or `None` for a threaded
+    /// run.
+    pub fn scheduler(self, seed: u64) -> Option<Box<dyn Scheduler>> {
+        match self {
+            TransportType::DiscreteFifo => Some(Box::new(FifoScheduler)),
+            TransportType::DiscreteRandom => Some(Box::new(RandomScheduler::new(seed, 10))),
+            TransportType::DiscreteAdversarial => {
+                Some(Box::new(AdversarialScheduler { hold_back: 10 }))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TransportType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportType::MessageChannel => write!(f, "message_channel"),
+            TransportType::ByteChannel => write!(f, "byte_channel"),
+            TransportType::Tcp => write!(f, "tcp"),
+            TransportType::DiscreteFifo => write!(f, "discrete_fifo"),
+            TransportType::DiscreteRandom => write!(f, "discrete_random"),
+            TransportType::DiscreteAdversarial => write!(f, "discrete_adversarial"),
+        }
+    }
+}
+
+impl FromStr for TransportType {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "message_channel" => Ok(TransportType::MessageChannel),
+            "byte_channel" => Ok(TransportType::ByteChannel),
+            "tcp" => Ok(TransportType::Tcp),
+            "discrete_fifo" => Ok(TransportType::DiscreteFifo),
+            "discrete_random" => Ok(TransportType::DiscreteRandom),
+            "discrete_adversarial" => Ok(TransportType::DiscreteAdversarial),
+            _ => Err("invalid transport type string"),
+        }
+    }
+}
+
+/// Everything about a run, as read from a TOML or JSON file:
+///
+/// ```toml
+/// transport = "message_channel"
+/// protocol = "ben_or"
+/// coin = "local"
+/// seed = 42
+///
+/// [[processes]]
+/// init = 0
+/// count = 2
+///
+/// [[processes]]
+/// init = 1
+/// behavior = "crashes"
+///
+/// [faults]
+/// latency = { uniform = [1, 5] }
+/// drop_probability = 0.01
+///
+/// [partitions]
+/// schedule = ["0,1|2@1..3"]
+///
+/// [termination]
+/// all_correct_decided = true
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub processes: Vec<ProcessGroup>,
+    /// How many faulty processes the correct ones tolerate. Defaults to the
+    /// number of processes that aren't correct.
+    #[serde(default)]
+    pub num_adversaries: Option<usize>,
+    #[serde(default)]
+    pub protocol: Protocol,
+    #[serde(default)]
+    pub coin: CoinType,
+    #[serde(default = "message_channel")]
+    pub transport: TransportType,
+    /// `run` uses 0 if there is none, while the binary draws a random seed
+    /// and prints it, so that the run can be replayed.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Only for the threaded transports.
+    #[serde(default)]
+    pub faults: Option<Faults>,
+    /// Only for the threaded transports.
+    #[serde(default)]
+    pub partitions: Option<PartitionSchedule>,
+    #[serde(default)]
+    pub termination: Termination,
+}
+
+fn message_channel() -> TransportType {
+    TransportType::MessageChannel
+}
+
+/// `count` processes configured alike.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProcessGroup {
+    #[serde(flatten)]
+    pub config: ProcessConfig,
+    #[serde(default = "one")]
+    pub count: usize,
+}
+
+fn one() -> usize {
+    1
+}
+
+impl FromStr for ProcessGroup {
+    type Err = &'static str;
+
+    /// Parses `<init>[:<behavior>][*<count>]`, e.g. `1:crashes*2`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '*');
+        let config = parts.next().ok_or("empty process")?;
+        let count = match parts.next() {
+            Some(count) => count.parse().map_err(|_| "count is not a number")?,
+            None => 1,
+        };
+        let mut parts = config.splitn(2, ':');
+        let init = parts
+            .next()
+            .ok_or("empty process")?
+            .parse::<u8>()
+            .map_err(|_| "init is not a value")?
+            .try_into()?;
+        let behavior = match parts.next() {
+            Some(behavior) => behavior.parse()?,
+            None => Behavior::Correct,
+        };
+        Ok(ProcessGroup {
+            config: ProcessConfig { init, behavior },
+            count,
+        })
+    }
+}
+
+impl Scenario {
+    /// The processes that `simulate` sets up, on the given transport.
+    pub fn for_simulation(
+        num_processes: usize,
+        num_zeros: usize,
+        num_adversaries: usize,
+        adversarial_behavior: &Behavior,
+        transport: TransportType,
+    ) -> Self {
+        let processes = ProcessConfig::for_simulation(
+            num_processes,
+            num_zeros,
+            num_adversaries,
+            adversarial_behavior,
+        );
+        Scenario {
+            processes: processes
+                .into_iter()
+                .map(|config| ProcessGroup { config, count: 1 })
+                .collect(),
+            num_adversaries: Some(num_adversaries),
+            protocol: Protocol::BenOr,
+            coin: CoinType::Local,
+            transport,
+            seed: None,
+            faults: None,
+            partitions: None,
+            termination: Termination::default(),
+        }
+    }
+
+    /// Reads a scenario from a JSON file if its name ends in `.json`, and
+    /// from a TOML file otherwise.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ScenarioError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path).map_err(ScenarioError::Io)?;
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            Self::from_json(&contents)
+        } else {
+            Self::from_toml(&contents)
+        }
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self, ScenarioError> {
+        let scenario: Self = toml::from_str(s).map_err(|e| ScenarioError::Parse(e.to_string()))?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, ScenarioError> {
+        let scenario: Self =
+            serde_json::from_str(s).map_err(|e| ScenarioError::Parse(e.to_string()))?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    /// Checks that the scenario can be run, as the file loaders do.
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        let num_processes = self.processes().len();
+        if num_processes == 0 {
+            return Err(ScenarioError::Invalid("there are no processes"));
+        }
+        if self.num_adversaries() >= num_processes {
+            return Err(ScenarioError::Invalid(
+                "num_adversaries must be less than the number of processes",
+            ));
+        }
+        if !self
+            .protocol
+            .tolerates(num_processes, self.num_adversaries())
+        {
+            return Err(ScenarioError::Invalid(
+                "ben_or_byzantine needs more than 5 times num_adversaries processes",
+            ));
+        }
+        if self.faults.is_some() && self.transport.scheduler(0).is_some() {
+            return Err(ScenarioError::Invalid(
+                "faults can't be injected into a discrete-event simulation",
+            ));
+        }
+        if let Some(faults) = &self.faults {
+            faults.validate().map_err(ScenarioError::Invalid)?;
+        }
+        if self.partitions.is_some() && self.transport.scheduler(0).is_some() {
+            return Err(ScenarioError::Invalid(
+                "a discrete-event simulation can't be partitioned",
+            ));
+        }
+        if let Some(partitions) = &self.partitions {
+            partitions
+                .validate(num_processes)
+                .map_err(ScenarioError::Invalid)?;
+        }
+        Ok(())
+    }
+
+    /// Every process, in the order of their ids.
+    pub fn processes(&self) -> Vec<ProcessConfig> {
+        self.processes
+            .iter()
+            .flat_map(|group| std::iter::repeat_n(group.config.clone(), group.count))
+            .collect()
+    }
+
+    pub fn num_adversaries(&self) -> usize {
+        self.num_adversaries.unwrap_or_else(|| {
+            self.processes()
+                .iter()
+                .filter(|config| !config.is_correct())
+                .count()
+        })
+    }
+
+    pub fn checker(&self) -> SafetyChecker {
+        SafetyChecker::for_processes(&self.processes())
+    }
+
+    pub fn run(&self) -> io::Result<ScenarioRun> {
+        self.start(None)
+    }
+
+    /// Like `run`, but records every send, receive, drop, skip, wait, coin
+    /// flip and decision of the processes in the returned trace.
+    pub fn run_traced(&self) -> io::Result<(ScenarioRun, Trace)> {
+        let trace = Trace::new();
+        let run = self.start(Some(&trace))?;
+        Ok((run, trace))
+    }
+
+    fn start(&self, trace: Option<&Trace>) -> io::Result<ScenarioRun> {
+        let processes = self.processes();
+        let num_processes = processes.len();
+        let seed = self.seed.unwrap_or_default();
+        let termination = self.termination.clone();
+        let algorithm = Algorithm {
+            protocol: self.protocol,
+            coin: self.coin,
+        };
+        if let Some(scheduler) = self.transport.scheduler(seed) {
+            return Ok(ScenarioRun::Discrete(Simulator::spawn(
+                processes,
+                self.num_adversaries(),
+                algorithm,
+                scheduler,
+                seed,
+                termination,
+                trace,
+            )));
+        }
+        let mut transports = self
+            .transport
+            .transports(num_processes)?
+            .expect("threaded transport");
+        // the faults apply to what comes out of the partitioned network,
+        // which delivers the messages within a group itself
+        if let Some(partitions) = &self.partitions {
+            transports = partitions.wrap(transports).0;
+        }
+        if let Some(faults) = &self.faults {
+            transports = FaultyTransport::wrap(transports, |_, _| faults.clone(), seed)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        }
+        Ok(ScenarioRun::Threads(Run::spawn(
+            processes,
+            self.num_adversaries(),
+            algorithm,
+            transports,
+            seed,
+            termination,
+            trace,
+        )))
+    }
+}
+
+/// A run of a scenario on either kind of transport.
+pub enum ScenarioRun {
+    Threads(Run),
+    Discrete(Simulator),
+}
+
+impl ScenarioRun {
+    pub fn summary(&self) -> Summary {
+        match self {
+            ScenarioRun::Threads(run) => run.summary(),
+            ScenarioRun::Discrete(simulator) => simulator.summary(),
+        }
+    }
+}
+
+impl Iterator for ScenarioRun {
+    type Item = (Id, Outcome);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            ScenarioRun::Threads(run) => run.next(),
+            ScenarioRun::Discrete(simulator) => simulator.next(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(io::Error),
+    Parse(String),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "{}", e),
+            ScenarioError::Parse(e) => write!(f, "{}", e),
+            ScenarioError::Invalid(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for ScenarioError {}
+
+impl From<io::Error> for ScenarioError {
+    fn from(e: io::Error) -> Self {
+        ScenarioError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::{
+        message::{Phase, Value},
+        transport::{CrossTraffic, Latency, Partition},
+    };
+
+    const TOML: &str = r#"
+        transport = "discrete_random"
+        seed = 3
+
+        [[processes]]
+        init = 0
+        count = 2
+
+        [[processes]]
+        init = 1
+        behavior = "crashes"
+
+        [[processes]]
+        init = 1
+        count = 2
+
+        [termination]
+        all_correct_decided = true
+    "#;
+
+    #[test]
+    fn parse_toml() {
+        let scenario = Scenario::from_toml(TOML).expect("parse");
+        assert_eq!(scenario.transport, TransportType::DiscreteRandom);
+        assert_eq!(scenario.num_adversaries(), 1);
+        let processes = scenario.processes();
+        assert_eq!(processes.len(), 5);
+        assert_eq!(
+            processes[2],
+            ProcessConfig {
+                init: Value::One,
+                behavior: Behavior::Crashes,
+            }
+        );
+        assert_eq!(processes[0].behavior, Behavior::Correct);
+        assert!(scenario.termination.all_correct_decided);
+
+        let scenario =
+            Scenario::from_toml("[[processes]]\ninit = 0\n[faults]\nlatency = { exponential = 3 }")
+                .expect("parse");
+        assert_eq!(
+            scenario.faults.expect("faults").latency,
+            Latency::Exponential(Duration::from_millis(3))
+        );
+    }
+
+    #[test]
+    fn parse_json() {
+        let scenario = Scenario::from_json(
+            r#"{
+                "processes": [{"init": 0}, {"init": 1}, {"init": 1, "behavior": "stops_executing"}],
+                "faults": {"latency": {"uniform": [1, 5]}, "drop_probability": 0.5}
+            }"#,
+        )
+        .expect("parse");
+        assert_eq!(scenario.transport, TransportType::MessageChannel);
+        assert_eq!(scenario.processes().len(), 3);
+        let faults = scenario.faults.expect("faults");
+        assert_eq!(
+            faults.latency,
+            Latency::Uniform(Duration::from_millis(1), Duration::from_millis(5))
+        );
+        assert_eq!(faults.drop_probability, 0.5);
+        assert_eq!(faults.duplicate_probability, 0.0);
+    }
+
+    #[test]
+    fn parse_process_group() {
+        assert_eq!(
+            "1:crashes*2".parse(),
+            Ok(ProcessGroup {
+                config: ProcessConfig {
+                    init: Value::One,
+                    behavior: Behavior::Crashes,
+                },
+                count: 2,
+            })
+        );
+        assert_eq!(
+            "0".parse(),
+            Ok(ProcessGroup {
+                config: ProcessConfig {
+                    init: Value::Zero,
+                    behavior: Behavior::Correct,
+                },
+                count: 1,
+            })
+        );
+        assert!("2".parse::<ProcessGroup>().is_err());
+        assert!("0:lies".parse::<ProcessGroup>().is_err());
+        assert!("0*x".parse::<ProcessGroup>().is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Scenario::from_toml("processes = []").is_err());
+        assert!(Scenario::from_toml("[[processes]]\ninit = 2").is_err());
+        assert!(Scenario::from_toml("[[processes]]\ninit = 0\ncolor = 1").is_err());
+        assert!(Scenario::from_toml(
+            "transport = \"discrete_fifo\"\n[faults]\n[[processes]]\ninit = 0"
+        )
+        .is_err());
+        for probability in &["drop", "duplicate", "reorder"] {
+            for value in &["-0.1", "1.5"] {
+                assert!(Scenario::from_toml(&format!(
+                    "[faults]\n{}_probability = {}\n[[processes]]\ninit = 0",
+                    probability, value
+                ))
+                .is_err());
+            }
+        }
+        assert!(
+            Scenario::from_toml("[faults]\ndrop_probability = 1.0\n[[processes]]\ninit = 0")
+                .is_ok()
+        );
+        let partitioned = |transport, schedule| {
+            Scenario::from_toml(&format!(
+                "transport = \"{}\"\n\
+                 [partitions]\nschedule = [\"{}\"]\n\
+                 [[processes]]\ninit = 0\ncount = 3",
+                transport, schedule
+            ))
+        };
+        assert!(partitioned("message_channel", "0|1,2@1..2").is_ok());
+        assert!(partitioned("discrete_fifo", "0|1,2@1..2").is_err());
+        assert!(partitioned("message_channel", "0|1..3@1..2").is_err());
+        assert!(partitioned("message_channel", "0|1,2@2..1").is_err());
+        // one equivocating process needs at least 5 correct ones
+        let byzantine = |num_correct| {
+            Scenario::from_toml(&format!(
+                "protocol = \"ben_or_byzantine\"\n\
+                 [[processes]]\ninit = 1\nbehavior = \"equivocates\"\n\
+                 [[processes]]\ninit = 0\ncount = {}",
+                num_correct
+            ))
+        };
+        assert!(byzantine(4).is_err());
+        assert!(byzantine(5).is_ok());
+    }
+
+    #[test]
+    fn run() {
+        let scenario = Scenario::from_toml(TOML).expect("parse");
+        let mut checker = scenario.checker();
+        let mut run = scenario.run().expect("start run");
+        let outcomes = run.by_ref().collect::<Vec<_>>();
+        assert_eq!(checker.check(&outcomes), vec![]);
+        assert!(run.summary().all_correct_decided);
+    }
+
+    #[test]
+    fn partitioned_run() {
+        let scenario = Scenario::from_toml(
+            r#"
+            seed = 7
+            num_adversaries = 2
+
+            [[processes]]
+            init = 0
+            count = 2
+
+            [[processes]]
+            init = 1
+            count = 3
+
+            [partitions]
+            schedule = ["0,1|2..4@1..3"]
+            cross_traffic = "buffer"
+            heal_after_ms = 60000
+
+            [termination]
+            all_correct_decided = true
+        "#,
+        )
+        .expect("parse");
+        let partitions = scenario.partitions.clone().expect("partitions");
+        assert_eq!(
+            partitions.schedule,
+            vec![Partition {
+                groups: vec![vec![0, 1], vec![2, 3, 4]],
+                from: Phase(1),
+                to: Phase(3),
+            }]
+        );
+        assert_eq!(partitions.cross_traffic, CrossTraffic::Buffer);
+        // the majority gets past the partition on its own, which heals it
+        // long before it would expire
+        let mut checker = scenario.checker();
+        let mut run = scenario.run().expect("start run");
+        let outcomes = run.by_ref().collect::<Vec<_>>();
+        assert_eq!(checker.check(&outcomes), vec![]);
+        assert!(run.summary().all_correct_decided);
+    }
+
+    #[test]
+    fn byzantine_runs_agree() {
+        for behavior in &[Behavior::Equivocates, Behavior::SendsInvalidMessages] {
+            let mut scenario =
+                Scenario::for_simulation(6, 3, 1, behavior, TransportType::DiscreteRandom);
+            scenario.protocol = Protocol::BenOrByzantine;
+            scenario.seed = Some(1);
+            scenario.termination = Termination {
+                all_correct_decided: true,
+                max_phase: Some(100),
+                ..Termination::default()
+            };
+            scenario.validate().expect("valid");
+            let mut checker = scenario.checker();
+            let mut run = scenario.run().expect("start run");
+            let outcomes = run.by_ref().collect::<Vec<_>>();
+            assert_eq!(checker.check(&outcomes), vec![], "{}", behavior);
+            assert!(run.summary().all_correct_decided, "{}", behavior);
+        }
+    }
+}
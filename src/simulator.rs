@@ -0,0 +1,442 @@
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::{BinaryHeap, VecDeque},
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    task::{self, Poll, Waker},
+    time::Duration,
+};
+
+use crate::{
+    message::Message,
+    outcome::Outcome,
+    process::{Id, ProcessConfig},
+    step::{Algorithm, Behavior},
+    termination::{Summary, Termination, Tracker},
+    trace::{Recorder, Trace},
+    transport::{broadcast, Stopper, Transport, TransportError},
+};
+
+mod scheduler;
+
+pub use scheduler::{AdversarialScheduler, Envelope, FifoScheduler, RandomScheduler, Scheduler};
+
+/// A discrete-event simulation of a run.
+///
+/// The processes run as tasks on the calling thread, driven by one loop: a
+/// process runs until it waits for a message, and then the simulator
+/// delivers the next message in virtual time and polls its recipient. Given
+/// the same seed and scheduler, a run is therefore fully reproducible.
+///
+/// There's no wall clock, so the processes only stall once no message is
+/// left in flight: they would wait forever for the ones they still need.
+pub struct Simulator {
+    now: u64,
+    num_sent: u64,
+    events: BinaryHeap<Event>,
+    scheduler: Box<dyn Scheduler>,
+    network: Arc<Mutex<Network>>,
+    // `None` once a process is done or has crashed
+    tasks: Vec<Option<Task>>,
+    outcomes: Rc<RefCell<VecDeque<(Id, Outcome)>>>,
+    tracker: Tracker,
+}
+
+type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+impl Simulator {
+    pub fn new(
+        num_processes: usize,
+        num_zeros: usize,
+        num_adversaries: usize,
+        adversarial_behavior: Behavior,
+        scheduler: Box<dyn Scheduler>,
+        seed: u64,
+        termination: Termination,
+    ) -> Self {
+        let processes = ProcessConfig::for_simulation(
+            num_processes,
+            num_zeros,
+            num_adversaries,
+            &adversarial_behavior,
+        );
+        Self::with_processes(processes, num_adversaries, scheduler, seed, termination)
+    }
+
+    /// Like `new`, but with every process configured on its own. The correct
+    /// processes tolerate up to `num_adversaries` faulty ones.
+    pub fn with_processes(
+        processes: Vec<ProcessConfig>,
+        num_adversaries: usize,
+        scheduler: Box<dyn Scheduler>,
+        seed: u64,
+        termination: Termination,
+    ) -> Self {
+        Self::spawn(
+            processes,
+            num_adversaries,
+            Algorithm::default(),
+            scheduler,
+            seed,
+            termination,
+            None,
+        )
+    }
+
+    pub(crate) fn spawn(
+        processes: Vec<ProcessConfig>,
+        num_adversaries: usize,
+        algorithm: Algorithm,
+        scheduler: Box<dyn Scheduler>,
+        seed: u64,
+        termination: Termination,
+        trace: Option<&Trace>,
+    ) -> Self {
+        let num_processes = processes.len();
+        assert!(num_adversaries < num_processes);
+        let correct = processes.iter().map(ProcessConfig::is_correct).collect();
+        let mut simulator = Simulator {
+            now: 0,
+            num_sent: 0,
+            events: BinaryHeap::new(),
+            scheduler,
+            network: Arc::new(Mutex::new(Network {
+                sent: vec![],
+                inboxes: vec![VecDeque::new(); num_processes],
+                idle: false,
+            })),
+            tasks: vec![],
+            outcomes: Rc::new(RefCell::new(VecDeque::new())),
+            tracker: Tracker::new(termination.clone(), correct),
+        };
+        for (id, config) in processes.into_iter().enumerate() {
+            let transport = SimulatedTransport {
+                id,
+                num_processes,
+                network: simulator.network.clone(),
+            };
+            let recorder = trace.map_or_else(|| Recorder::off(id), |trace| trace.recorder(id));
+            let mut outcomes = crate::start_process(
+                id,
+                config,
+                num_adversaries,
+                algorithm,
+                Box::new(transport),
+                seed,
+                &termination,
+                recorder,
+            );
+            let mut limit = termination.limiter();
+            let reported = simulator.outcomes.clone();
+            simulator.tasks.push(Some(Box::pin(async move {
+                while let Some(outcome) = outcomes.next_outcome().await {
+                    let stops = limit.stops_with(&outcome);
+                    reported.borrow_mut().push_back((Id(id), outcome));
+                    if stops {
+                        return;
+                    }
+                }
+            })));
+            // start the processes one at a time so that their first sends
+            // are ordered deterministically
+            simulator.poll(id);
+        }
+        simulator
+    }
+
+    /// How far every process got so far.
+    pub fn summary(&self) -> Summary {
+        self.tracker.summary()
+    }
+
+    /// The current virtual time.
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Delivers the next message and runs its recipient until it waits
+    /// again, or stalls the processes still waiting if there are no messages
+    /// left in flight. Returns `false` once there's nothing left to do.
+    pub fn step(&mut self) -> bool {
+        let Event { time, envelope, .. } = match self.events.pop() {
+            Some(event) => event,
+            None => return self.stall(),
+        };
+        self.now = time;
+        if self.tasks[envelope.to].is_some() {
+            self.network.lock().expect("lock").inboxes[envelope.to]
+                .push_back((envelope.from, envelope.message));
+            self.poll(envelope.to);
+        }
+        true
+    }
+
+    // times out the wait of every process that's still running, which ends it
+    fn stall(&mut self) -> bool {
+        let waiting = (0..self.tasks.len())
+            .filter(|id| self.tasks[*id].is_some())
+            .collect::<Vec<_>>();
+        if waiting.is_empty() {
+            return false;
+        }
+        self.network.lock().expect("lock").idle = true;
+        for id in waiting {
+            self.poll(id);
+        }
+        self.network.lock().expect("lock").idle = false;
+        true
+    }
+
+    // runs the process until it waits for a message again, and schedules
+    // what it sent meanwhile
+    fn poll(&mut self, id: usize) {
+        if let Some(task) = &mut self.tasks[id] {
+            let mut cx = task::Context::from_waker(Waker::noop());
+            // a crash is a panic, which ends the process as it would end
+            // its thread
+            match panic::catch_unwind(AssertUnwindSafe(|| task.as_mut().poll(&mut cx))) {
+                Ok(Poll::Pending) => {}
+                Ok(Poll::Ready(())) | Err(_) => self.tasks[id] = None,
+            }
+        }
+        let sent = std::mem::take(&mut self.network.lock().expect("lock").sent);
+        for envelope in sent {
+            let time = self.now + self.scheduler.delay(self.now, &envelope);
+            self.events.push(Event {
+                time,
+                seq: self.num_sent,
+                envelope,
+            });
+            self.num_sent += 1;
+        }
+    }
+}
+
+impl Iterator for Simulator {
+    type Item = (Id, Outcome);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.tracker.done() {
+            return None;
+        }
+        while self.outcomes.borrow().is_empty() {
+            if !self.step() {
+                return None;
+            }
+        }
+        let (id, outcome) = self.outcomes.borrow_mut().pop_front()?;
+        self.tracker.observe(&id, &outcome);
+        Some((id, outcome))
+    }
+}
+
+struct Event {
+    time: u64,
+    seq: u64,
+    envelope: Envelope,
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+        (self.time, self.seq) == (other.time, other.seq)
+    }
+}
+
+impl Eq for Event {}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Event {
+    // reversed, so that the earliest event is at the top of the heap
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.time, other.seq).cmp(&(self.time, self.seq))
+    }
+}
+
+// what the simulated transports share with the simulator
+struct Network {
+    // sent since the simulator last scheduled the messages
+    sent: Vec<Envelope>,
+    // delivered but not yet received, per process
+    inboxes: Vec<VecDeque<(usize, Message)>>,
+    // whether nothing is left in flight, so that waiting times out
+    idle: bool,
+}
+
+struct SimulatedTransport {
+    id: usize,
+    num_processes: usize,
+    network: Arc<Mutex<Network>>,
+}
+
+impl Transport for SimulatedTransport {
+    fn num_senders(&self) -> usize {
+        self.num_processes
+    }
+
+    fn send(&self, message: Message) -> Result<(), TransportError> {
+        broadcast(self.id, self.num_processes, |to| {
+            self.send_to(to, message.clone())
+        })
+    }
+
+    fn send_to(&self, to: usize, message: Message) -> Result<(), TransportError> {
+        if to >= self.num_processes {
+            return Err(TransportError::NoSuchProcess(to));
+        }
+        self.network.lock().expect("lock").sent.push(Envelope {
+            from: self.id,
+            to,
+            message,
+        });
+        Ok(())
+    }
+
+    fn send_to_self(&self, message: Message) -> Result<(), TransportError> {
+        self.send_to(self.id, message)
+    }
+
+    // a simulated process has no thread of its own to block, so it only
+    // ever waits through `poll_receive`
+    fn receive(&self) -> Result<(usize, Message), TransportError> {
+        unreachable!("simulated processes are polled")
+    }
+
+    fn receive_timeout(&self, _timeout: Duration) -> Result<(usize, Message), TransportError> {
+        self.receive()
+    }
+
+    // there's no wall clock in a simulation: a wait times out once nothing
+    // is left in flight, whatever its timeout, and only then
+    fn poll_receive(
+        &self,
+        _timeout: Option<Duration>,
+    ) -> Poll<Result<(usize, Message), TransportError>> {
+        let mut network = self.network.lock().expect("lock");
+        match network.inboxes[self.id].pop_front() {
+            Some(received) => Poll::Ready(Ok(received)),
+            None if network.idle => Poll::Ready(Err(TransportError::Timeout)),
+            None => Poll::Pending,
+        }
+    }
+
+    fn try_receive(&self) -> Result<Option<(usize, Message)>, TransportError> {
+        Ok(self.network.lock().expect("lock").inboxes[self.id].pop_front())
+    }
+    // nothing ever blocks, and a simulation stops by no longer polling
+    fn stopper(&self) -> Stopper {
+        Box::new(|| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::message::{Phase, Value};
+
+    fn run(scheduler: Box<dyn Scheduler>, seed: u64) -> Vec<(usize, Outcome)> {
+        Simulator::new(
+            5,
+            2,
+            2,
+            Behavior::RandomlyAdversarial,
+            scheduler,
+            seed,
+            Termination::default(),
+        )
+        .take(40)
+        .map(|(id, outcome)| (id.0, outcome))
+        .collect()
+    }
+
+    #[test]
+    fn runs_are_reproducible() {
+        for seed in 0..4 {
+            assert_eq!(
+                run(Box::new(FifoScheduler), seed),
+                run(Box::new(FifoScheduler), seed)
+            );
+            assert_eq!(
+                run(Box::new(RandomScheduler::new(seed, 10)), seed),
+                run(Box::new(RandomScheduler::new(seed, 10)), seed)
+            );
+            assert_eq!(
+                run(Box::new(AdversarialScheduler { hold_back: 10 }), seed),
+                run(Box::new(AdversarialScheduler { hold_back: 10 }), seed)
+            );
+        }
+    }
+
+    #[test]
+    fn random_schedulers_may_not_delay() {
+        let envelope = Envelope {
+            from: 0,
+            to: 1,
+            message: Message::Report {
+                phase: Phase(1),
+                value: Value::One,
+            },
+        };
+        let mut scheduler = RandomScheduler::new(0, 0);
+        assert_eq!(scheduler.delay(3, &envelope), 0);
+        let mut scheduler = RandomScheduler::new(0, 1);
+        assert_eq!(scheduler.delay(3, &envelope), 1);
+    }
+
+    #[test]
+    fn processes_stall_once_nothing_is_in_flight() {
+        // none of them is tolerated to fail, so the others stall once the
+        // crashing one is gone
+        let processes = vec![
+            ProcessConfig {
+                init: Value::One,
+                behavior: Behavior::Crashes,
+            },
+            ProcessConfig {
+                init: Value::One,
+                behavior: Behavior::Correct,
+            },
+            ProcessConfig {
+                init: Value::One,
+                behavior: Behavior::Correct,
+            },
+        ];
+        let mut simulator = Simulator::with_processes(
+            processes,
+            0,
+            Box::new(FifoScheduler),
+            0,
+            Termination::default(),
+        );
+        simulator.by_ref().for_each(drop);
+        let stalls = simulator.summary().stalls;
+        assert!(stalls[1..].iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn virtual_time_advances() {
+        let mut simulator = Simulator::new(
+            3,
+            1,
+            0,
+            Behavior::Correct,
+            Box::new(FifoScheduler),
+            0,
+            Termination::default(),
+        );
+        assert_eq!(simulator.now(), 0);
+        assert!(simulator.step());
+        assert_eq!(simulator.now(), 1);
+        assert!(simulator.next().is_some());
+        assert!(simulator.now() > 1);
+    }
+}
@@ -0,0 +1,75 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::message::{Message, Value};
+
+/// A message in flight from one process to another.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Envelope {
+    pub from: usize,
+    pub to: usize,
+    pub message: Message,
+}
+
+/// Decides how long each message spends in flight, in ticks of virtual time.
+///
+/// Messages are delivered in the order of their delivery time, and in the
+/// order they were sent when the delivery times are equal.
+pub trait Scheduler: Send {
+    fn delay(&mut self, now: u64, envelope: &Envelope) -> u64;
+}
+
+/// Delivers messages in the order they were sent.
+pub struct FifoScheduler;
+
+impl Scheduler for FifoScheduler {
+    fn delay(&mut self, _now: u64, _envelope: &Envelope) -> u64 {
+        1
+    }
+}
+
+/// Delays every message by a random number of ticks, up to `max_delay`. A
+/// `max_delay` of 0 delivers every message right away, in the order sent.
+pub struct RandomScheduler {
+    rng: StdRng,
+    max_delay: u64,
+}
+
+impl RandomScheduler {
+    pub fn new(seed: u64, max_delay: u64) -> Self {
+        RandomScheduler {
+            rng: StdRng::seed_from_u64(seed),
+            max_delay,
+        }
+    }
+}
+
+impl Scheduler for RandomScheduler {
+    fn delay(&mut self, _now: u64, _envelope: &Envelope) -> u64 {
+        if self.max_delay == 0 {
+            return 0;
+        }
+        self.rng.gen_range(0, self.max_delay) + 1
+    }
+}
+
+/// Tries to keep the processes split by holding back ones from the even
+/// processes and zeros from the odd ones, so that each half sees a
+/// different majority first.
+pub struct AdversarialScheduler {
+    pub hold_back: u64,
+}
+
+impl Scheduler for AdversarialScheduler {
+    fn delay(&mut self, _now: u64, envelope: &Envelope) -> u64 {
+        let held_back = match envelope.message.value() {
+            Some(Value::One) => envelope.to.is_multiple_of(2),
+            Some(Value::Zero) => !envelope.to.is_multiple_of(2),
+            None => false,
+        };
+        if held_back {
+            self.hold_back
+        } else {
+            1
+        }
+    }
+}
@@ -1,39 +1,66 @@
-use std::{fmt, str::FromStr};
+use std::{
+    collections::HashSet,
+    fmt, future,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, Rng};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, trace, warn};
 
 use crate::{
+    coin::CoinType,
     message::{Message, Phase, Value},
-    outcome::{Context, Decision},
-    transport::Transport,
+    outcome::{Context, Decision, Stall, Step, StepFn},
+    trace::{Event, Recorder},
+    transport::{Transport, TransportError},
 };
 
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Behavior {
     Correct,
     Crashes,
     SendsInvalidMessages,
+    SendsRandomBytes,
     StopsExecuting,
     RandomlyAdversarial,
+    Equivocates,
 }
 
 impl Behavior {
-    pub(crate) fn step_fn(&self) -> impl Fn(&Context, Phase, Value, usize) -> Decision {
+    pub(crate) fn step_fn(&self, rng: &mut StdRng) -> StepFn {
         match self {
             Behavior::Correct => correct,
             Behavior::Crashes => randomly_crashes,
             Behavior::SendsInvalidMessages => randomly_sends_invalid_messages,
+            Behavior::SendsRandomBytes => randomly_sends_random_bytes,
             Behavior::StopsExecuting => randomly_stops_executing,
+            Behavior::Equivocates => equivocates,
             Behavior::RandomlyAdversarial => {
                 use rand::seq::SliceRandom;
-                [
-                    randomly_crashes,
+                *[
+                    randomly_crashes as StepFn,
                     randomly_sends_invalid_messages,
                     randomly_stops_executing,
                 ]
-                .choose(&mut rand::thread_rng())
+                .choose(rng)
                 .expect("choose")
-                .clone()
             }
         }
     }
+
+    // crashing or skipping steps never makes a process lie about its value
+    pub(crate) fn withholds_only(&self) -> bool {
+        match self {
+            Behavior::Correct | Behavior::Crashes | Behavior::StopsExecuting => true,
+            Behavior::SendsInvalidMessages
+            | Behavior::SendsRandomBytes
+            | Behavior::RandomlyAdversarial
+            | Behavior::Equivocates => false,
+        }
+    }
 }
 
 impl fmt::Display for Behavior {
@@ -42,8 +69,10 @@ impl fmt::Display for Behavior {
             Behavior::Correct => write!(f, "correct",),
             Behavior::Crashes => write!(f, "crashes",),
             Behavior::SendsInvalidMessages => write!(f, "sends_invalid_messages",),
+            Behavior::SendsRandomBytes => write!(f, "sends_random_bytes",),
             Behavior::StopsExecuting => write!(f, "stops_executing",),
             Behavior::RandomlyAdversarial => write!(f, "randomly_adversarial",),
+            Behavior::Equivocates => write!(f, "equivocates",),
         }
     }
 }
@@ -56,63 +85,191 @@ impl FromStr for Behavior {
             "correct" => Ok(Behavior::Correct),
             "crashes" => Ok(Behavior::Crashes),
             "sends_invalid_messages" => Ok(Behavior::SendsInvalidMessages),
+            "sends_random_bytes" => Ok(Behavior::SendsRandomBytes),
             "stops_executing" => Ok(Behavior::StopsExecuting),
             "randomly_adversarial" => Ok(Behavior::RandomlyAdversarial),
+            "equivocates" => Ok(Behavior::Equivocates),
             _ => Err("invalid behavior string"),
         }
     }
 }
 
+/// The variant of the algorithm the correct processes follow, which sets how
+/// many matching messages each step of a phase needs.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    /// Tolerates up to f crashed processes: proposes a value reported by
+    /// more than n/2 processes and decides it once f + 1 propose it.
+    #[default]
+    BenOr,
+    /// Tolerates up to f byzantine processes as long as n > 5f: proposes a
+    /// value reported by more than (n + f)/2 processes, adopts it once f + 1
+    /// propose it and decides it once more than (n + f)/2 do.
+    BenOrByzantine,
+}
+
+impl Protocol {
+    /// Whether the correct processes stay safe and live with up to
+    /// `num_adversaries` faulty processes out of `num_processes`.
+    pub fn tolerates(self, num_processes: usize, num_adversaries: usize) -> bool {
+        match self {
+            Protocol::BenOr => num_processes > num_adversaries,
+            Protocol::BenOrByzantine => num_processes > 5 * num_adversaries,
+        }
+    }
+
+    fn quorums(self, num_processes: usize, num_adversaries: usize) -> Quorums {
+        match self {
+            Protocol::BenOr => Quorums {
+                propose: num_processes / 2 + 1,
+                adopt: 1,
+                decide: num_adversaries + 1,
+            },
+            Protocol::BenOrByzantine => Quorums {
+                propose: (num_processes + num_adversaries) / 2 + 1,
+                adopt: num_adversaries + 1,
+                decide: (num_processes + num_adversaries) / 2 + 1,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::BenOr => write!(f, "ben_or"),
+            Protocol::BenOrByzantine => write!(f, "ben_or_byzantine"),
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ben_or" => Ok(Protocol::BenOr),
+            "ben_or_byzantine" => Ok(Protocol::BenOrByzantine),
+            _ => Err("invalid protocol string"),
+        }
+    }
+}
+
+// the variant of the algorithm every process of a run follows, and the coin
+// it flips
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Algorithm {
+    pub(crate) protocol: Protocol,
+    pub(crate) coin: CoinType,
+}
+
+// how many matching messages, with the same v, each step of a phase needs
+struct Quorums {
+    propose: usize,
+    adopt: usize,
+    decide: usize,
+}
+
 fn correct(
-    context: &Context,
+    context: &mut Context,
     current_phase: Phase,
     current_value: Value,
     num_adversaries: usize,
-) -> Decision {
-    let Context { id, transport } = context;
+) -> Step<'_> {
+    Box::pin(follow(
+        context,
+        current_phase,
+        current_value,
+        num_adversaries,
+        |transport, message| transport.send(message),
+    ))
+}
+
+// the algorithm, with `broadcast` sending each of its messages
+async fn follow(
+    context: &mut Context,
+    current_phase: Phase,
+    current_value: Value,
+    num_adversaries: usize,
+    broadcast: fn(&dyn Transport, Message) -> Result<(), TransportError>,
+) -> Result<Decision, TransportError> {
+    let Context {
+        transport,
+        rng,
+        deferred,
+        stall_timeout,
+        protocol,
+        coin,
+        recorder,
+        ..
+    } = context;
+    let transport: &dyn Transport = transport.as_ref();
     let num_processes = transport.num_senders();
-    assert!(num_processes > num_adversaries);
+    assert!(protocol.tolerates(num_processes, num_adversaries));
+    let quorums = protocol.quorums(num_processes, num_adversaries);
 
     // send (R, k, x) to all processes
-    eprintln!(
-        "Process {}: send (R, {}, {}) to all processes",
-        id.0, current_phase.0, current_value
-    );
-    transport.send(Message::Report {
-        phase: current_phase,
-        value: current_value.clone(),
-    });
+    debug!(step = "send R", value = %current_value, "send (R, k, x) to all processes");
+    broadcast(
+        transport,
+        Message::Report {
+            phase: current_phase,
+            value: current_value.clone(),
+        },
+    )?;
 
     // wait for messages of the form (R, k, *) from n - f
     // processes {"*" can be 0 or 1}
-    eprintln!(
-        "Process {}: wait for messages of the form (R, {}, *) from {}",
-        id.0,
-        current_phase.0,
-        num_processes - num_adversaries
+    let num_reports = num_processes - num_adversaries;
+    debug!(
+        step = "wait R",
+        need = num_reports,
+        "wait for messages of the form (R, k, *) from n - f processes"
     );
-    let (ones, zeros) = read_values(transport, num_processes - num_adversaries, |message| {
-        eprintln!("Process {}: Received {:?}", id.0, message);
-        match &message {
+    let wait = Wait {
+        phase: current_phase,
+        waiting_for: 'R',
+        need: num_reports,
+        timeout: *stall_timeout,
+    };
+    let read = read_values(transport, deferred, recorder, wait, |from, message| {
+        trace!(from, ?message, "received");
+        match message {
             Message::Report { phase, value } => {
                 if phase == &current_phase {
-                    Some(Some(value.clone()))
+                    Handling::Count(Some(value.clone()))
+                } else if phase > &current_phase {
+                    trace!(from, ?message, "deferred");
+                    Handling::Defer
                 } else {
-                    eprintln!("Process {}: dropped {:?}", id.0, message);
-                    None
+                    trace!(from, ?message, "dropped");
+                    Handling::Drop
                 }
             }
             Message::Proposal { phase, value: _ } => {
                 if phase >= &current_phase {
-                    transport.send_to_self(message.clone());
-                    eprintln!("Process {}: skipped {:?}", id.0, message);
+                    trace!(from, ?message, "deferred");
+                    Handling::Defer
                 } else {
-                    eprintln!("Process {}: dropped {:?}", id.0, message);
+                    trace!(from, ?message, "dropped");
+                    Handling::Drop
                 }
-                None
             }
+            Message::Candidate { .. } | Message::Instance { .. } | Message::Decided { .. } => {
+                trace!(from, ?message, "dropped");
+                Handling::Drop
+            }
+        }
+    })
+    .await?;
+    let (ones, zeros) = match read {
+        Ok(values) => values,
+        Err(stall) => {
+            warn!(step = "stall", %stall, "stalled");
+            return Ok(Decision::Stalled(stall));
         }
-    });
+    };
     let mut potentials = if ones.len() > zeros.len() {
         ones
     } else {
@@ -120,65 +277,90 @@ fn correct(
     };
 
     // if received more than n/2 (R, k, v) with the same v
-    eprintln!(
-        "Process {}: if received more than {} (R, {}, v) with the same v",
-        id.0,
-        num_processes / 2,
-        current_phase.0
+    // {more than (n + f)/2 against byzantine processes}
+    debug!(
+        count = potentials.len(),
+        need = quorums.propose,
+        "if received more than n/2 (R, k, v) with the same v"
     );
-    if potentials.len() > num_processes / 2 {
+    if potentials.len() >= quorums.propose {
         let potential = potentials.pop().expect("at least one");
         // then send (P, k, v) to all processes
-        eprintln!(
-            "Process {}: then send (P, {}, {}) to all processes",
-            id.0, current_phase.0, potential
-        );
-        transport.send(Message::Proposal {
-            phase: current_phase,
-            value: Some(potential.clone()),
-        });
+        debug!(step = "send P", value = %potential, "then send (P, k, v) to all processes");
+        broadcast(
+            transport,
+            Message::Proposal {
+                phase: current_phase,
+                value: Some(potential.clone()),
+            },
+        )?;
     } else {
         // else send (P, k, ?) to all processes
-        eprintln!(
-            "Process {}: else send (P, {}, ?) to all processes",
-            id.0, current_phase.0
+        debug!(
+            step = "send P",
+            value = "?",
+            "else send (P, k, ?) to all processes"
         );
-        transport.send(Message::Proposal {
-            phase: current_phase,
-            value: None,
-        });
+        broadcast(
+            transport,
+            Message::Proposal {
+                phase: current_phase,
+                value: None,
+            },
+        )?;
     }
 
     // wait for messages of the form (P, k, *) from n - f
     // processes {"*" can be 0 or 1}
-    eprintln!(
-        "Process {}: wait for messages of the form (P, {}, *) from {}",
-        id.0,
-        current_phase.0,
-        num_processes - num_adversaries
+    let num_proposals = num_processes - num_adversaries;
+    debug!(
+        step = "wait P",
+        need = num_proposals,
+        "wait for messages of the form (P, k, *) from n - f processes"
     );
-    let (ones, zeros) = read_values(transport, num_processes - num_adversaries, |message| {
-        eprintln!("Process {}: Received {:?}", id.0, message);
-        match &message {
+    let wait = Wait {
+        phase: current_phase,
+        waiting_for: 'P',
+        need: num_proposals,
+        timeout: *stall_timeout,
+    };
+    let read = read_values(transport, deferred, recorder, wait, |from, message| {
+        trace!(from, ?message, "received");
+        match message {
             Message::Proposal { phase, value } => {
                 if phase == &current_phase {
-                    Some(value.clone())
+                    Handling::Count(value.clone())
+                } else if phase > &current_phase {
+                    trace!(from, ?message, "deferred");
+                    Handling::Defer
                 } else {
-                    eprintln!("Process {}: dropped {:?}", id.0, message);
-                    None
+                    trace!(from, ?message, "dropped");
+                    Handling::Drop
                 }
             }
             Message::Report { phase, value: _ } => {
                 if phase > &current_phase {
-                    transport.send_to_self(message.clone());
-                    eprintln!("Process {}: skipped {:?}", id.0, message);
+                    trace!(from, ?message, "deferred");
+                    Handling::Defer
                 } else {
-                    eprintln!("Process {}: dropped {:?}", id.0, message);
+                    trace!(from, ?message, "dropped");
+                    Handling::Drop
                 }
-                None
             }
+            Message::Candidate { .. } | Message::Instance { .. } | Message::Decided { .. } => {
+                trace!(from, ?message, "dropped");
+                Handling::Drop
+            }
+        }
+    })
+    .await?;
+    let (ones, zeros) = match read {
+        Ok(values) => values,
+        Err(stall) => {
+            warn!(step = "stall", %stall, "stalled");
+            return Ok(Decision::Stalled(stall));
         }
-    });
+    };
     let mut potentials = if ones.len() > zeros.len() {
         ones
     } else {
@@ -189,130 +371,538 @@ fn correct(
     let potential = potentials.pop();
 
     // if at least one (P, k, v) with v != ?
-    eprintln!(
-        "Process {}: if at least one (P, {}, v) with v != ?",
-        id.0, current_phase.0
+    // {at least f + 1 against byzantine processes}
+    debug!(
+        value = ?potential,
+        count = num_potentials,
+        need = quorums.adopt,
+        "if at least one (P, k, v) with v != ?"
     );
-    let next = if let &Some(ref value) = &potential {
+    let next = if let Some(value) = potential
+        .as_ref()
+        .filter(|_| num_potentials >= quorums.adopt)
+    {
         // TODO: this is less general because we pick the majority
 
         // then x <- v
-        eprintln!("Process {}: then x <- {}", id.0, value);
+        debug!(step = "adopt", value = %value, "then x <- v");
         value.clone()
-    } else if rand::random::<bool>() {
-        // else x <- 1 randomly {query r.n.g}
-        eprintln!("Process {}: else x <- 1 randomly", id.0);
-        Value::One
     } else {
-        // else x <- 0 randomly {query r.n.g}
-        eprintln!("Process {}: else x <- 0 randomly", id.0);
-        Value::Zero
+        // else x <- 0 or 1 randomly {query r.n.g, or the common coin}
+        let value = coin.flip(current_phase, rng);
+        debug!(step = "coin", value = %value, "else x <- 0 or 1 randomly");
+        recorder.record((
+            current_phase,
+            Event::Coin {
+                value: value.clone(),
+            },
+        ));
+        value
     };
 
     // if received at least f + 1 (P, k, v) with the same v != ?
-    eprintln!(
-        "Process {}: if received at least f + 1 (P, {}, v) with the same v != ?",
-        id.0, current_phase.0
+    // {more than (n + f)/2 against byzantine processes}
+    debug!(
+        count = num_potentials,
+        need = quorums.decide,
+        "if received at least f + 1 (P, k, v) with the same v != ?"
     );
-    if num_potentials > num_adversaries {
+    if num_potentials >= quorums.decide {
         // then decide(v)
-        eprintln!(
-            "Process {}: then decide({})",
-            id.0,
-            potential.clone().expect("exists")
+        info!(
+            step = "decide",
+            value = %potential.clone().expect("exists"),
+            "then decide(v)"
         );
-        Decision::Done {
+        recorder.record((
+            current_phase,
+            Event::Decide {
+                value: potential.clone().expect("exists"),
+            },
+        ));
+        Ok(Decision::Done {
             next,
             decided: potential.expect("exists"),
-        }
+        })
     } else {
         // else send (P, k, ?) to all processes
-        eprintln!(
-            "Process {}: else send (P, {}, ?) to all processes",
-            id.0, current_phase.0
-        );
-        Decision::Pending { next }
+        debug!(step = "undecided", "else go on to the next phase");
+        Ok(Decision::Pending { next })
     }
 }
 
-fn read_values(
-    transport: &Box<dyn Transport>,
-    take: usize,
-    filter_map_fn: impl Fn(Message) -> Option<Option<Value>>,
-) -> (Vec<Value>, Vec<Value>) {
+// what a wait does with each message it receives
+enum Handling {
+    Count(Option<Value>),
+    // keep it for a later wait
+    Defer,
+    Drop,
+}
+
+// the ones and the zeros counted by a wait
+type Values = (Vec<Value>, Vec<Value>);
+
+// how many distinct senders a wait needs, and how long it may go without
+// hearing from a new one
+struct Wait {
+    phase: Phase,
+    waiting_for: char,
+    need: usize,
+    timeout: Option<Duration>,
+}
+
+// the deferred messages are handled before any new ones, and each of them
+// only once per wait, so that a process that can't make progress blocks on
+// the transport instead of spinning on its own messages
+//
+// the wait counts distinct senders rather than messages, so that a process
+// repeating itself can't make up a quorum on its own, and only a new sender
+// restarts its timeout, so that it can't keep the wait alive either
+async fn read_values(
+    transport: &dyn Transport,
+    deferred: &mut Vec<(usize, Message)>,
+    recorder: &Recorder,
+    wait: Wait,
+    handle: impl Fn(usize, &Message) -> Handling,
+) -> Result<Result<Values, Stall>, TransportError> {
     let mut ones = vec![];
     let mut zeros = vec![];
-    let mut count: usize = 0;
-    while count < take {
-        let message = transport.receive();
-        if let Some(value) = filter_map_fn(message) {
-            count = count + 1;
-            if let Some(Value::One) = value {
-                ones.push(Value::One);
-            } else if let Some(Value::Zero) = value {
-                zeros.push(Value::Zero);
+    let mut counted = HashSet::new();
+    let mut pending = std::mem::take(deferred).into_iter();
+    let mut deadline = wait.timeout.map(|timeout| Instant::now() + timeout);
+    let waiting = |have| Event::Wait {
+        kind: wait.waiting_for,
+        have,
+        need: wait.need,
+    };
+    recorder.record((wait.phase, waiting(0)));
+    let mut stalled = false;
+    while counted.len() < wait.need {
+        let received = match pending.next() {
+            Some(received) => Ok(received),
+            None => {
+                let timeout =
+                    deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+                future::poll_fn(|_| transport.poll_receive(timeout)).await
+            }
+        };
+        let (from, message) = match received {
+            Ok(received) => received,
+            Err(TransportError::Timeout) => {
+                stalled = true;
+                break;
             }
+            Err(e) => return Err(e),
+        };
+        match handle(from, &message) {
+            Handling::Count(_) if counted.contains(&from) => {
+                debug!(from, ?message, "ignored a repeated sender");
+                recorder.record(Event::drop(from, &message));
+            }
+            Handling::Count(value) => {
+                counted.insert(from);
+                recorder.record((wait.phase, waiting(counted.len())));
+                deadline = wait.timeout.map(|timeout| Instant::now() + timeout);
+                if let Some(Value::One) = value {
+                    ones.push(Value::One);
+                } else if let Some(Value::Zero) = value {
+                    zeros.push(Value::Zero);
+                }
+            }
+            Handling::Defer => {
+                recorder.record(Event::skip(from, &message));
+                deferred.push((from, message));
+            }
+            Handling::Drop => recorder.record(Event::drop(from, &message)),
         }
     }
-    (ones, zeros)
+    let mut unread = pending.collect::<Vec<_>>();
+    unread.append(deferred);
+    *deferred = unread;
+    if stalled {
+        return Ok(Err(Stall {
+            waiting_for: wait.waiting_for,
+            have: counted.len(),
+            need: wait.need,
+        }));
+    }
+    Ok(Ok((ones, zeros)))
 }
 
 fn randomly_crashes(
-    context: &Context,
+    context: &mut Context,
     current_phase: Phase,
     current_value: Value,
     num_adversaries: usize,
-) -> Decision {
-    if rand::random::<u64>() % (current_phase.0 + 2) == 0 {
-        panic!("Process {}: Crashing", context.id.0)
-    } else {
-        correct(context, current_phase, current_value, num_adversaries)
-    }
+) -> Step<'_> {
+    Box::pin(async move {
+        if context.rng.gen::<u64>().is_multiple_of(current_phase.0 + 2) {
+            panic!("Process {}: Crashing", context.id.0)
+        } else {
+            correct(context, current_phase, current_value, num_adversaries).await
+        }
+    })
 }
 
 fn randomly_sends_invalid_messages(
-    context: &Context,
+    context: &mut Context,
     current_phase: Phase,
     current_value: Value,
     num_adversaries: usize,
-) -> Decision {
-    if rand::random::<bool>() {
-        context.transport.send(if rand::random::<bool>() {
-            Message::Proposal {
-                phase: current_phase,
-                value: if rand::random::<bool>() {
-                    Some(current_value.clone())
-                } else {
-                    None
-                },
-            }
+) -> Step<'_> {
+    Box::pin(async move {
+        if context.rng.gen::<bool>() {
+            context.transport.send(if context.rng.gen::<bool>() {
+                Message::Proposal {
+                    phase: current_phase,
+                    value: if context.rng.gen::<bool>() {
+                        Some(current_value.clone())
+                    } else {
+                        None
+                    },
+                }
+            } else {
+                Message::Report {
+                    phase: current_phase,
+                    value: current_value.clone(),
+                }
+            })?;
+            info!(step = "adversary", "sent random messages");
+            Ok(Decision::Pending {
+                next: current_value,
+            })
         } else {
-            Message::Report {
-                phase: current_phase,
-                value: current_value.clone(),
+            correct(context, current_phase, current_value, num_adversaries).await
+        }
+    })
+}
+
+fn randomly_sends_random_bytes(
+    context: &mut Context,
+    current_phase: Phase,
+    current_value: Value,
+    num_adversaries: usize,
+) -> Step<'_> {
+    Box::pin(async move {
+        if context.rng.gen::<bool>() {
+            let Context { transport, rng, .. } = context;
+            for to in 0..transport.num_senders() {
+                // about as long as an encoded message, so that a few of them decode
+                let len = rng.gen_range(0, 12);
+                let bytes = (0..len).map(|_| rng.gen()).collect();
+                if let Err(e) = transport.send_bytes(to, bytes) {
+                    warn!(to, error = %e, "failed to send");
+                }
             }
-        });
-        eprintln!("Process {}: Sent random messages", context.id.0);
-        Decision::Pending {
-            next: current_value,
+            info!(step = "adversary", "sent random bytes");
+            Ok(Decision::Pending {
+                next: current_value,
+            })
+        } else {
+            correct(context, current_phase, current_value, num_adversaries).await
         }
-    } else {
-        correct(context, current_phase, current_value, num_adversaries)
-    }
+    })
 }
 
 fn randomly_stops_executing(
-    context: &Context,
+    context: &mut Context,
     current_phase: Phase,
     current_value: Value,
     num_adversaries: usize,
-) -> Decision {
-    if rand::random::<bool>() {
-        eprintln!("Process {}: Stopped executing", context.id.0);
-        Decision::Pending {
-            next: current_value,
+) -> Step<'_> {
+    Box::pin(async move {
+        if context.rng.gen::<bool>() {
+            info!(step = "adversary", "stopped executing");
+            Ok(Decision::Pending {
+                next: current_value,
+            })
+        } else {
+            correct(context, current_phase, current_value, num_adversaries).await
+        }
+    })
+}
+
+// follows the algorithm's waits, so that it keeps pace with the correct
+// processes, but tells the even processes 0 and the odd ones 1 in every
+// report and proposal
+fn equivocates(
+    context: &mut Context,
+    current_phase: Phase,
+    current_value: Value,
+    num_adversaries: usize,
+) -> Step<'_> {
+    Box::pin(follow(
+        context,
+        current_phase,
+        current_value,
+        num_adversaries,
+        equivocate,
+    ))
+}
+
+fn equivocate(transport: &dyn Transport, message: Message) -> Result<(), TransportError> {
+    for to in 0..transport.num_senders() {
+        let value = if to % 2 == 0 { Value::Zero } else { Value::One };
+        let lie = match &message {
+            Message::Report { phase, .. } => Message::Report {
+                phase: *phase,
+                value,
+            },
+            Message::Proposal { phase, .. } => Message::Proposal {
+                phase: *phase,
+                value: Some(value),
+            },
+            // only the binary algorithm lies
+            message => message.clone(),
+        };
+        if let Err(e) = transport.send_to(to, lie) {
+            warn!(to, error = %e, "failed to send");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::SeedableRng;
+
+    use crate::{
+        checker::SafetyChecker,
+        outcome::{block_on, ProcessId},
+        process::ProcessConfig,
+        simulator::{RandomScheduler, Simulator},
+        termination::Termination,
+        transport::MessageChannel,
+    };
+
+    #[test]
+    fn equivocators_tell_processes_different_values() {
+        let transports = MessageChannel::new(3);
+        equivocate(
+            transports[0].as_ref(),
+            Message::Report {
+                phase: Phase(1),
+                value: Value::One,
+            },
+        )
+        .expect("send");
+        let values = transports
+            .iter()
+            .map(|transport| match transport.try_receive() {
+                Ok(Some((0, Message::Report { value, .. }))) => value,
+                message => panic!("unexpected {:?}", message),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![Value::Zero, Value::One, Value::Zero]);
+        assert!(transports.iter().all(|t| t.try_receive() == Ok(None)));
+    }
+
+    // runs with process 0 equivocating, as the number of them that broke
+    // agreement or validity, and the number that left a correct process
+    // undecided
+    fn runs_with_an_equivocator(protocol: Protocol, num_processes: usize) -> (usize, usize) {
+        let processes = ProcessConfig::for_simulation(
+            num_processes,
+            num_processes / 2,
+            1,
+            &Behavior::Equivocates,
+        );
+        let termination = Termination {
+            max_phase: Some(100),
+            all_correct_decided: true,
+            ..Termination::default()
+        };
+        let (mut unsafe_runs, mut undecided_runs) = (0, 0);
+        for seed in 0..20 {
+            let mut simulator = Simulator::spawn(
+                processes.clone(),
+                1,
+                Algorithm {
+                    protocol,
+                    coin: CoinType::Local,
+                },
+                Box::new(RandomScheduler::new(seed, 10)),
+                seed,
+                termination.clone(),
+                None,
+            );
+            let mut checker = SafetyChecker::for_processes(&processes);
+            let violations = (&mut simulator)
+                .filter(|(id, outcome)| checker.observe(id, outcome).is_err())
+                .count();
+            if violations > 0 {
+                unsafe_runs += 1;
+            }
+            if !simulator.summary().all_correct_decided {
+                undecided_runs += 1;
+            }
+        }
+        (unsafe_runs, undecided_runs)
+    }
+
+    #[test]
+    fn equivocation_is_only_tolerated_by_ben_or_byzantine() {
+        // ben_or counts on its faulty processes to only crash, so an
+        // equivocator can talk the others into deciding differently
+        let (unsafe_runs, _) = runs_with_an_equivocator(Protocol::BenOr, 4);
+        assert!(unsafe_runs > 0);
+        // while ben_or_byzantine's quorums outweigh it
+        assert_eq!(
+            runs_with_an_equivocator(Protocol::BenOrByzantine, 6),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn deferred_messages_are_kept_aside_rather_than_re_queued() {
+        let transports = MessageChannel::new(2);
+        let report = |phase| Message::Report {
+            phase: Phase(phase),
+            value: Value::One,
+        };
+        transports[1].send_to(0, report(1)).expect("send");
+        let mut deferred = vec![(1, report(2))];
+        let wait = Wait {
+            phase: Phase(1),
+            waiting_for: 'R',
+            need: 1,
+            timeout: None,
+        };
+        let (ones, zeros) = block_on(read_values(
+            transports[0].as_ref(),
+            &mut deferred,
+            &Recorder::off(0),
+            wait,
+            |_, message| match message {
+                Message::Report {
+                    phase: Phase(1),
+                    value,
+                } => Handling::Count(Some(value.clone())),
+                _ => Handling::Defer,
+            },
+        ))
+        .expect("read")
+        .expect("no stall");
+        assert_eq!((ones.len(), zeros.len()), (1, 0));
+        assert_eq!(deferred, vec![(1, report(2))]);
+        assert_eq!(transports[0].try_receive(), Ok(None));
+    }
+
+    #[test]
+    fn a_flooding_process_counts_once() {
+        let transports = MessageChannel::new(4);
+        let report = |value| Message::Report {
+            phase: Phase(0),
+            value,
+        };
+        for _ in 0..5 {
+            transports[3].send_to(0, report(Value::One)).expect("send");
+        }
+        transports[2].send_to(0, report(Value::Zero)).expect("send");
+        transports[0]
+            .send_to_self(report(Value::Zero))
+            .expect("send");
+
+        let wait = Wait {
+            phase: Phase(0),
+            waiting_for: 'R',
+            need: 3,
+            timeout: None,
+        };
+        let (ones, zeros) = block_on(read_values(
+            transports[0].as_ref(),
+            &mut vec![],
+            &Recorder::off(0),
+            wait,
+            count_reports,
+        ))
+        .expect("read")
+        .expect("no stall");
+        assert_eq!((ones.len(), zeros.len()), (1, 2));
+    }
+
+    #[test]
+    fn a_wait_stalls_when_too_few_processes_are_heard_from() {
+        let transports = MessageChannel::new(3);
+        transports[0]
+            .send_to_self(Message::Report {
+                phase: Phase(0),
+                value: Value::One,
+            })
+            .expect("send");
+        let wait = Wait {
+            phase: Phase(0),
+            waiting_for: 'R',
+            need: 2,
+            timeout: Some(Duration::from_millis(20)),
+        };
+        let stall = block_on(read_values(
+            transports[0].as_ref(),
+            &mut vec![],
+            &Recorder::off(0),
+            wait,
+            count_reports,
+        ))
+        .expect("read")
+        .expect_err("stall");
+        assert_eq!(stall.to_string(), "waiting for R messages, have 1 of 2");
+    }
+
+    #[test]
+    fn proposals_of_a_later_phase_are_kept_for_it() {
+        let mut transports = MessageChannel::new(3);
+        let report = |phase| Message::Report {
+            phase: Phase(phase),
+            value: Value::One,
+        };
+        let proposal = |phase| Message::Proposal {
+            phase: Phase(phase),
+            value: Some(Value::One),
+        };
+        // processes 1 and 2 get to phase 2 and then stop, so their proposals
+        // of phase 2 arrive while process 0 still waits for those of phase 1,
+        // and it has to keep them to decide
+        for message in [report(1), proposal(2), report(2)] {
+            transports[1].send_to(0, message.clone()).expect("send");
+            transports[2].send_to(0, message).expect("send");
+        }
+        transports[1]
+            .send_to(
+                0,
+                Message::Proposal {
+                    phase: Phase(1),
+                    value: None,
+                },
+            )
+            .expect("send");
+        let mut context = Context {
+            id: ProcessId(0),
+            transport: transports.remove(0),
+            rng: StdRng::seed_from_u64(0),
+            deferred: vec![],
+            stall_timeout: Some(Duration::from_millis(20)),
+            protocol: Protocol::BenOr,
+            coin: CoinType::Local.coin(0),
+            recorder: Recorder::off(0),
+        };
+        let first = block_on(correct(&mut context, Phase(1), Value::One, 1)).expect("phase 1");
+        assert!(matches!(first, Decision::Pending { next: Value::One }));
+        let second = block_on(correct(&mut context, Phase(2), Value::One, 1)).expect("phase 2");
+        assert!(matches!(
+            second,
+            Decision::Done {
+                decided: Value::One,
+                ..
+            }
+        ));
+    }
+
+    fn count_reports(_: usize, message: &Message) -> Handling {
+        match message {
+            Message::Report { value, .. } => Handling::Count(Some(value.clone())),
+            _ => Handling::Drop,
         }
-    } else {
-        correct(context, current_phase, current_value, num_adversaries)
     }
 }
@@ -0,0 +1,308 @@
+use std::{io, str::FromStr};
+
+use crate::{
+    batch::{Batch, BatchReport},
+    checker::Violation,
+    coin::CoinType,
+    scenario::TransportType,
+    step::{Behavior, Protocol},
+    termination::{Summary, Termination},
+};
+
+/// One end of a `Bounds`: a number, or an expression on the number of
+/// processes like `n`, `n/2` or `n-1`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bound {
+    Fixed(usize),
+    Processes(Op, usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl Bound {
+    fn eval(self, num_processes: usize) -> usize {
+        match self {
+            Bound::Fixed(value) => value,
+            Bound::Processes(Op::Add, k) => num_processes + k,
+            Bound::Processes(Op::Sub, k) => num_processes.saturating_sub(k),
+            Bound::Processes(Op::Mul, k) => num_processes * k,
+            Bound::Processes(Op::Div, k) => num_processes / k,
+        }
+    }
+}
+
+impl FromStr for Bound {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if !s.starts_with('n') {
+            return s.parse().map(Bound::Fixed).map_err(|_| "not a number");
+        }
+        let rest = s[1..].trim();
+        if rest.is_empty() {
+            return Ok(Bound::Processes(Op::Mul, 1));
+        }
+        let expected_op = "expected one of + - * / after n";
+        let symbol = rest.chars().next().ok_or(expected_op)?;
+        let op = match symbol {
+            '+' => Op::Add,
+            '-' => Op::Sub,
+            '*' => Op::Mul,
+            '/' => Op::Div,
+            _ => return Err(expected_op),
+        };
+        let k = rest[symbol.len_utf8()..]
+            .trim()
+            .parse()
+            .map_err(|_| "not a number")?;
+        if op == Op::Div && k == 0 {
+            return Err("division by zero");
+        }
+        Ok(Bound::Processes(op, k))
+    }
+}
+
+/// An inclusive range like `4..32` or `0..n/2`, or a single value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub first: Bound,
+    pub last: Bound,
+}
+
+impl Bounds {
+    /// The values for a run with `num_processes` processes.
+    pub fn values(&self, num_processes: usize) -> std::ops::RangeInclusive<usize> {
+        self.first.eval(num_processes)..=self.last.eval(num_processes)
+    }
+}
+
+impl FromStr for Bounds {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut bounds = s.splitn(2, "..");
+        let first = bounds.next().ok_or("empty range")?.parse()?;
+        let last = match bounds.next() {
+            Some(last) => last.parse()?,
+            None => first,
+        };
+        // a range that depends on n can only be checked once n is known
+        if let (Bound::Fixed(first), Bound::Fixed(last)) = (first, last) {
+            if first > last {
+                return Err("range ends before it starts");
+            }
+        }
+        Ok(Bounds { first, last })
+    }
+}
+
+/// Batches of runs over every combination of the parameters.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sweep {
+    pub num_processes: Bounds,
+    pub num_adversaries: Bounds,
+    pub num_zeros: Bounds,
+    pub behaviors: Vec<Behavior>,
+    pub transports: Vec<TransportType>,
+    pub coins: Vec<CoinType>,
+    pub runs: usize,
+    pub max_phase: u64,
+    pub seed: u64,
+}
+
+impl Sweep {
+    /// Every combination, as a batch and the transport type to run it on.
+    /// Combinations that ben_or doesn't tolerate, with no processes or no
+    /// fewer adversaries than processes, and those with more zeros than
+    /// processes are skipped.
+    pub fn batches(&self) -> Vec<(Batch, TransportType)> {
+        let mut batches = vec![];
+        // the number of processes can't depend on itself
+        for num_processes in self.num_processes.values(0) {
+            for num_adversaries in self.num_adversaries.values(num_processes) {
+                for num_zeros in self.num_zeros.values(num_processes) {
+                    if !Protocol::BenOr.tolerates(num_processes, num_adversaries)
+                        || num_zeros > num_processes
+                    {
+                        continue;
+                    }
+                    for behavior in &self.behaviors {
+                        for transport in &self.transports {
+                            for coin in &self.coins {
+                                let batch = Batch {
+                                    num_processes,
+                                    num_zeros,
+                                    num_adversaries,
+                                    behavior: behavior.clone(),
+                                    coin: *coin,
+                                    runs: self.runs,
+                                    max_phase: self.max_phase,
+                                    seed: self.seed,
+                                };
+                                batches.push((batch, *transport));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        batches
+    }
+
+    /// Runs every batch with `run`, as `Batch::run_with` does, writing a row
+    /// to the table as soon as a batch is done.
+    pub fn run_with<E: From<io::Error>>(
+        &self,
+        mut run: impl FnMut(
+            &Batch,
+            TransportType,
+            u64,
+            Termination,
+        ) -> Result<(Summary, Vec<Violation>), E>,
+        mut table: impl io::Write,
+    ) -> Result<(), E> {
+        writeln!(
+            table,
+            "n,f,zeros,behavior,transport,coin,runs,unterminated,unterminated_fraction,unsafe_runs,mean,median,p99,max"
+        )?;
+        for (batch, transport) in self.batches() {
+            let report =
+                batch.run_with(|seed, termination| run(&batch, transport, seed, termination))?;
+            write_row(&mut table, &batch, transport, &report)?;
+            table.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn write_row(
+    table: &mut impl io::Write,
+    batch: &Batch,
+    transport: TransportType,
+    report: &BatchReport,
+) -> io::Result<()> {
+    write!(
+        table,
+        "{},{},{},{},{},{},{},{},{},{},",
+        batch.num_processes,
+        batch.num_adversaries,
+        batch.num_zeros,
+        batch.behavior,
+        transport,
+        batch.coin,
+        report.runs,
+        report.unterminated,
+        report.unterminated_fraction(),
+        report.unsafe_runs
+    )?;
+    match report.overall() {
+        Some(stats) => writeln!(
+            table,
+            "{},{},{},{}",
+            stats.mean, stats.median, stats.p99, stats.max
+        ),
+        None => writeln!(table, ",,,"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::simulator::{FifoScheduler, Simulator};
+
+    #[test]
+    fn parse_bounds() {
+        assert_eq!(
+            "0..n/2".parse(),
+            Ok(Bounds {
+                first: Bound::Fixed(0),
+                last: Bound::Processes(Op::Div, 2),
+            })
+        );
+        let bounds: Bounds = "n-2..n".parse().expect("parse");
+        assert_eq!(bounds.values(7), 5..=7);
+        assert_eq!("3".parse::<Bounds>().expect("parse").values(7), 3..=3);
+        assert!("n%2".parse::<Bounds>().is_err());
+        assert!("0..n/0".parse::<Bounds>().is_err());
+        assert!("né".parse::<Bounds>().is_err());
+        assert!("8..4".parse::<Bounds>().is_err());
+    }
+
+    fn sweep() -> Sweep {
+        Sweep {
+            num_processes: "3..4".parse().expect("parse"),
+            num_adversaries: "0..n/2".parse().expect("parse"),
+            num_zeros: "n/2".parse().expect("parse"),
+            behaviors: vec![Behavior::Crashes],
+            transports: vec![TransportType::DiscreteFifo],
+            coins: vec![CoinType::Local],
+            runs: 2,
+            max_phase: 30,
+            seed: 0,
+        }
+    }
+
+    #[test]
+    fn batches() {
+        let params = sweep()
+            .batches()
+            .into_iter()
+            .map(|(batch, _)| (batch.num_processes, batch.num_adversaries, batch.num_zeros))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            params,
+            vec![(3, 0, 1), (3, 1, 1), (4, 0, 2), (4, 1, 2), (4, 2, 2)]
+        );
+    }
+
+    #[test]
+    fn batches_skip_what_cannot_run() {
+        let sweep = Sweep {
+            num_processes: "0..2".parse().expect("parse"),
+            num_adversaries: "0..n".parse().expect("parse"),
+            num_zeros: "0".parse().expect("parse"),
+            ..sweep()
+        };
+        let params = sweep
+            .batches()
+            .into_iter()
+            .map(|(batch, _)| (batch.num_processes, batch.num_adversaries))
+            .collect::<Vec<_>>();
+        assert_eq!(params, vec![(1, 0), (2, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn table() {
+        let mut table = vec![];
+        sweep()
+            .run_with(
+                |batch, _, seed, termination| {
+                    let mut simulator = Simulator::new(
+                        batch.num_processes,
+                        batch.num_zeros,
+                        batch.num_adversaries,
+                        batch.behavior.clone(),
+                        Box::new(FifoScheduler),
+                        seed,
+                        termination,
+                    );
+                    let violations = batch.check(&mut simulator);
+                    Ok::<_, io::Error>((simulator.summary(), violations))
+                },
+                &mut table,
+            )
+            .expect("run sweep");
+        let table = String::from_utf8(table).expect("utf-8");
+        let rows = table.lines().collect::<Vec<_>>();
+        assert_eq!(rows.len(), 6);
+        assert!(rows[1].starts_with("3,0,1,crashes,discrete_fifo,local,2,0,0,0,"));
+    }
+}
@@ -0,0 +1,346 @@
+use std::{fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    message::{Phase, Value},
+    outcome::{Outcome, Stall},
+    process::Id,
+    transport::TransportError,
+};
+
+/// When a run stops. The run stops as soon as any of the configured
+/// conditions holds; by default it never does.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Termination {
+    /// Every process stops this many phases after it first decides. It
+    /// should be at least 1 so that the others can decide too.
+    pub extra_phases_after_decision: Option<u64>,
+    /// Every process stops after this phase.
+    pub max_phase: Option<u64>,
+    /// The whole run stops once every correct process has decided.
+    pub all_correct_decided: bool,
+    /// A process stalls when it waits this many milliseconds without hearing
+    /// from another process it needs. By default it waits forever. In a
+    /// discrete-event simulation it stalls once no message is left in flight
+    /// instead.
+    pub stall_timeout_ms: Option<u64>,
+}
+
+impl Termination {
+    /// Wraps the outcomes of a single process so that they end where this
+    /// policy stops the process. No further phase is run after that.
+    pub(crate) fn limit(
+        &self,
+        mut outcomes: impl Iterator<Item = (Id, Outcome)>,
+    ) -> impl Iterator<Item = (Id, Outcome)> {
+        let mut limit = self.limiter();
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let (id, outcome) = outcomes.next()?;
+            done = limit.stops_with(&outcome);
+            Some((id, outcome))
+        })
+    }
+
+    /// Like `limit`, for outcomes that aren't an iterator: tells outcome by
+    /// outcome whether the process stops.
+    pub(crate) fn limiter(&self) -> Limiter {
+        Limiter {
+            termination: self.clone(),
+            decided_in: None,
+        }
+    }
+
+    pub(crate) fn stall_timeout(&self) -> Option<Duration> {
+        self.stall_timeout_ms.map(Duration::from_millis)
+    }
+
+    fn stops_process(&self, phase: Phase, decided_in: Option<Phase>) -> bool {
+        let after_decision = match (self.extra_phases_after_decision, decided_in) {
+            (Some(extra), Some(decided_in)) => phase.0 >= decided_in.0 + extra,
+            _ => false,
+        };
+        let after_max = match self.max_phase {
+            Some(max_phase) => phase.0 >= max_phase,
+            None => false,
+        };
+        after_decision || after_max
+    }
+}
+
+pub(crate) struct Limiter {
+    termination: Termination,
+    decided_in: Option<Phase>,
+}
+
+impl Limiter {
+    /// Whether the process stops with this outcome, which is its next one.
+    pub(crate) fn stops_with(&mut self, outcome: &Outcome) -> bool {
+        if self.decided_in.is_none() && outcome.decided().is_some() {
+            self.decided_in = Some(outcome.phase());
+        }
+        self.termination
+            .stops_process(outcome.phase(), self.decided_in)
+    }
+}
+
+/// How far every process got in a run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Summary {
+    /// The phase in which each process first decided, and what.
+    pub decisions: Vec<Option<(Phase, Value)>>,
+    /// The last phase each process completed.
+    pub last_phases: Vec<Option<Phase>>,
+    /// What made each process fail, for those that did.
+    pub failures: Vec<Option<TransportError>>,
+    /// The phase in which each process stalled, and on what, for those that
+    /// did.
+    pub stalls: Vec<Option<(Phase, Stall)>>,
+    pub all_correct_decided: bool,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.all_correct_decided {
+            writeln!(f, "Summary: all correct processes decided")?;
+        } else {
+            writeln!(f, "Summary: not all correct processes decided")?;
+        }
+        for (id, (((decision, last_phase), failure), stall)) in self
+            .decisions
+            .iter()
+            .zip(&self.last_phases)
+            .zip(&self.failures)
+            .zip(&self.stalls)
+            .enumerate()
+        {
+            match decision {
+                Some((phase, value)) => {
+                    write!(f, "Process {}: decided {} in phase {}", id, value, phase.0)?
+                }
+                None => write!(f, "Process {}: undecided", id)?,
+            }
+            match last_phase {
+                Some(phase) => write!(f, ", last phase {}", phase.0)?,
+                None => write!(f, ", no phase completed")?,
+            }
+            if let Some(e) = failure {
+                write!(f, ", failed: {}", e)?;
+            }
+            if let Some((phase, stall)) = stall {
+                write!(f, ", stalled in phase {} {}", phase.0, stall)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Follows the outcomes of a run to tell when it should stop as a whole,
+/// and to sum it up, also for processes that run elsewhere.
+pub struct Tracker {
+    termination: Termination,
+    correct: Vec<bool>,
+    decisions: Vec<Option<(Phase, Value)>>,
+    last_phases: Vec<Option<Phase>>,
+    failures: Vec<Option<TransportError>>,
+    stalls: Vec<Option<(Phase, Stall)>>,
+    stopped: Vec<bool>,
+}
+
+impl Tracker {
+    /// Follows the processes for which `correct` tells whether they follow
+    /// the algorithm.
+    pub fn new(termination: Termination, correct: Vec<bool>) -> Self {
+        let num_processes = correct.len();
+        Tracker {
+            termination,
+            correct,
+            decisions: vec![None; num_processes],
+            last_phases: vec![None; num_processes],
+            failures: vec![None; num_processes],
+            stalls: vec![None; num_processes],
+            stopped: vec![false; num_processes],
+        }
+    }
+
+    pub fn observe(&mut self, id: &Id, outcome: &Outcome) {
+        if let Some(e) = outcome.failure() {
+            self.failures[id.0] = Some(e.clone());
+            return;
+        }
+        if let Some(stall) = outcome.stall() {
+            self.stalls[id.0] = Some((outcome.phase(), stall.clone()));
+            return;
+        }
+        self.last_phases[id.0] = Some(outcome.phase());
+        if self.decisions[id.0].is_none() {
+            if let Some(decided) = outcome.decided() {
+                self.decisions[id.0] = Some((outcome.phase(), decided));
+            }
+        }
+    }
+
+    /// Records that a process will report no further outcomes.
+    pub(crate) fn stopped(&mut self, id: usize) {
+        self.stopped[id] = true;
+    }
+
+    fn all_correct_decided(&self) -> bool {
+        self.correct
+            .iter()
+            .zip(&self.decisions)
+            .all(|(correct, decision)| !correct || decision.is_some())
+    }
+
+    // once the correct processes are all gone there's nothing left to wait
+    // for, even if some faulty ones are still going
+    pub(crate) fn done(&self) -> bool {
+        let all_correct_stopped = self
+            .correct
+            .iter()
+            .zip(&self.stopped)
+            .all(|(correct, stopped)| !correct || *stopped);
+        all_correct_stopped || self.termination.all_correct_decided && self.all_correct_decided()
+    }
+
+    pub fn summary(&self) -> Summary {
+        Summary {
+            decisions: self.decisions.clone(),
+            last_phases: self.last_phases.clone(),
+            failures: self.failures.clone(),
+            stalls: self.stalls.clone(),
+            all_correct_decided: self.all_correct_decided(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::outcome::Decision;
+
+    fn outcomes() -> impl Iterator<Item = (Id, Outcome)> {
+        (0..).map(|phase| {
+            let decision = if phase >= 3 {
+                Decision::Done {
+                    next: Value::One,
+                    decided: Value::One,
+                }
+            } else {
+                Decision::Pending { next: Value::Zero }
+            };
+            (
+                Id(0),
+                Outcome {
+                    phase: Phase(phase),
+                    decision,
+                },
+            )
+        })
+    }
+
+    #[test]
+    fn limit() {
+        let phases = |termination: Termination| {
+            termination
+                .limit(outcomes())
+                .map(|(_, outcome)| outcome.phase().0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            phases(Termination {
+                extra_phases_after_decision: Some(2),
+                ..Termination::default()
+            }),
+            vec![0, 1, 2, 3, 4, 5]
+        );
+        assert_eq!(
+            phases(Termination {
+                max_phase: Some(1),
+                ..Termination::default()
+            }),
+            vec![0, 1]
+        );
+        assert_eq!(
+            phases(Termination {
+                extra_phases_after_decision: Some(0),
+                max_phase: Some(7),
+                ..Termination::default()
+            }),
+            vec![0, 1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn tracker() {
+        let mut tracker = Tracker::new(
+            Termination {
+                all_correct_decided: true,
+                ..Termination::default()
+            },
+            vec![true, false],
+        );
+        for (id, outcome) in outcomes().take(3) {
+            tracker.observe(&id, &outcome);
+        }
+        assert!(!tracker.done());
+        for (id, outcome) in outcomes().skip(3).take(2) {
+            tracker.observe(&id, &outcome);
+        }
+        assert!(tracker.done());
+        assert!(!Tracker::new(Termination::default(), vec![true, false]).done());
+        let mut tracker = Tracker::new(Termination::default(), vec![true, false]);
+        tracker.stopped(0);
+        assert!(tracker.done());
+    }
+
+    #[test]
+    fn summary() {
+        let mut tracker = Tracker::new(Termination::default(), vec![true, false]);
+        for (id, outcome) in outcomes().take(5) {
+            tracker.observe(&id, &outcome);
+        }
+        tracker.observe(
+            &Id(1),
+            &Outcome {
+                phase: Phase(1),
+                decision: Decision::Failed(TransportError::Disconnected),
+            },
+        );
+        let stall = Stall {
+            waiting_for: 'P',
+            have: 1,
+            need: 2,
+        };
+        tracker.observe(
+            &Id(0),
+            &Outcome {
+                phase: Phase(5),
+                decision: Decision::Stalled(stall.clone()),
+            },
+        );
+        let summary = tracker.summary();
+        assert_eq!(
+            summary,
+            Summary {
+                decisions: vec![Some((Phase(3), Value::One)), None],
+                last_phases: vec![Some(Phase(4)), None],
+                failures: vec![None, Some(TransportError::Disconnected)],
+                stalls: vec![Some((Phase(5), stall)), None],
+                all_correct_decided: true,
+            }
+        );
+        assert!(summary.to_string().ends_with(
+            "Process 0: decided 1 in phase 3, last phase 4, \
+             stalled in phase 5 waiting for P messages, have 1 of 2\n\
+             Process 1: undecided, no phase completed, failed: disconnected\n"
+        ));
+    }
+}
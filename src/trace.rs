@@ -0,0 +1,457 @@
+use std::{
+    convert::TryFrom,
+    io::{self, BufRead, Write},
+    sync::mpsc::{Receiver, Sender, TryRecvError},
+    task::Poll,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    message::{Message, Phase, Value},
+    transport::{Stopper, Transport, TransportError},
+};
+
+/// One thing a process did in a run, as a line of a JSON Lines trace, e.g.
+/// `{"process":0,"phase":1,"event":"send","to":2,"kind":"R","value":1}`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TraceEvent {
+    pub process: usize,
+    /// The phase of the message, or of the process for coin flips and
+    /// decisions.
+    pub phase: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl TraceEvent {
+    /// Reads back a trace written by `Trace::write_jsonl`.
+    pub fn read_jsonl(reader: impl BufRead) -> io::Result<Vec<Self>> {
+        reader
+            .lines()
+            .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+            .map(|line| {
+                serde_json::from_str(&line?)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            })
+            .collect()
+    }
+}
+
+/// What a process did. `kind` is `'R'` for a report and `'P'` for a
+/// proposal, whose value is `None` for `?`, and `'C'` for a candidate and
+/// `'D'` for a decision of multi-valued consensus.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Send {
+        to: usize,
+        kind: char,
+        value: Option<Value>,
+    },
+    Receive {
+        from: usize,
+        kind: char,
+        value: Option<Value>,
+    },
+    /// A message the process had no use for.
+    Drop {
+        from: usize,
+        kind: char,
+        value: Option<Value>,
+    },
+    /// A message for a later wait, which the process keeps until then.
+    Skip {
+        from: usize,
+        kind: char,
+        value: Option<Value>,
+    },
+    /// The process waits for messages of the given kind from `need`
+    /// processes, and has heard from `have` of them so far.
+    Wait {
+        kind: char,
+        have: usize,
+        need: usize,
+    },
+    /// The process picked its next value at random.
+    Coin {
+        value: Value,
+    },
+    Decide {
+        value: Value,
+    },
+}
+
+impl Event {
+    pub(crate) fn send(to: usize, message: &Message) -> (Phase, Self) {
+        let (phase, kind, value) = describe(message);
+        (phase, Event::Send { to, kind, value })
+    }
+
+    pub(crate) fn receive(from: usize, message: &Message) -> (Phase, Self) {
+        let (phase, kind, value) = describe(message);
+        (phase, Event::Receive { from, kind, value })
+    }
+
+    pub(crate) fn drop(from: usize, message: &Message) -> (Phase, Self) {
+        let (phase, kind, value) = describe(message);
+        (phase, Event::Drop { from, kind, value })
+    }
+
+    pub(crate) fn skip(from: usize, message: &Message) -> (Phase, Self) {
+        let (phase, kind, value) = describe(message);
+        (phase, Event::Skip { from, kind, value })
+    }
+}
+
+fn describe(message: &Message) -> (Phase, char, Option<Value>) {
+    match message {
+        Message::Report { phase, value } => (*phase, 'R', Some(value.clone())),
+        Message::Proposal { phase, value } => (*phase, 'P', value.clone()),
+        Message::Candidate { .. } => (Phase(0), 'C', None),
+        Message::Instance { message, .. } => describe(message),
+        Message::Decided { phase, value, .. } => (*phase, 'D', Some(value.clone())),
+    }
+}
+
+/// Collects the events of a run started with `Scenario::run_traced`, in the
+/// order the processes recorded them. A discrete-event simulation records
+/// them in the same order every time it is run with the same seed.
+pub struct Trace {
+    sender: Sender<TraceEvent>,
+    receiver: Receiver<TraceEvent>,
+}
+
+impl Trace {
+    pub(crate) fn new() -> Self {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        Trace { sender, receiver }
+    }
+
+    pub(crate) fn recorder(&self, process: usize) -> Recorder {
+        Recorder {
+            process,
+            events: Some(self.sender.clone()),
+        }
+    }
+
+    /// The events recorded since the last call.
+    pub fn events(&self) -> Vec<TraceEvent> {
+        self.receiver.try_iter().collect()
+    }
+
+    /// Writes the events recorded since the last call, one JSON object per
+    /// line.
+    pub fn write_jsonl(&self, mut writer: impl Write) -> io::Result<()> {
+        for event in self.receiver.try_iter() {
+            serde_json::to_writer(&mut writer, &event)?;
+            writeln!(writer)?;
+        }
+        writer.flush()
+    }
+
+    /// Writes the events one JSON object per line as the processes record
+    /// them, until the run that records them is dropped, so that a run that
+    /// never ends still leaves its trace behind.
+    pub fn stream_jsonl(self, mut writer: impl Write) -> io::Result<()> {
+        let Trace { sender, receiver } = self;
+        drop(sender);
+        loop {
+            let event = match receiver.try_recv() {
+                Ok(event) => event,
+                Err(TryRecvError::Empty) => {
+                    // nothing more for now, so what's written so far is out
+                    // before waiting for the next one
+                    writer.flush()?;
+                    match receiver.recv() {
+                        Ok(event) => event,
+                        Err(_) => break,
+                    }
+                }
+                Err(TryRecvError::Disconnected) => break,
+            };
+            serde_json::to_writer(&mut writer, &event)?;
+            writeln!(writer)?;
+        }
+        writer.flush()
+    }
+}
+
+/// Records the events of one process into a `Trace`, if there is one.
+#[derive(Clone)]
+pub(crate) struct Recorder {
+    process: usize,
+    events: Option<Sender<TraceEvent>>,
+}
+
+impl Recorder {
+    pub(crate) fn off(process: usize) -> Self {
+        Recorder {
+            process,
+            events: None,
+        }
+    }
+
+    pub(crate) fn record(&self, (phase, event): (Phase, Event)) {
+        if let Some(events) = &self.events {
+            let _ = events.send(TraceEvent {
+                process: self.process,
+                phase: phase.0,
+                event,
+            });
+        }
+    }
+
+    /// Decorates the transport so that it records every message the process
+    /// sends and receives.
+    pub(crate) fn wrap(&self, transport: Box<dyn Transport>) -> Box<dyn Transport> {
+        if self.events.is_none() {
+            return transport;
+        }
+        Box::new(RecordingTransport {
+            inner: transport,
+            recorder: self.clone(),
+        })
+    }
+}
+
+// sends are recorded before they happen and receives after, so that every
+// receive comes after its send in the trace
+struct RecordingTransport {
+    inner: Box<dyn Transport>,
+    recorder: Recorder,
+}
+
+impl RecordingTransport {
+    fn received(
+        &self,
+        received: Result<(usize, Message), TransportError>,
+    ) -> Result<(usize, Message), TransportError> {
+        if let Ok((from, message)) = &received {
+            self.recorder.record(Event::receive(*from, message));
+        }
+        received
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn num_senders(&self) -> usize {
+        self.inner.num_senders()
+    }
+
+    fn send(&self, message: Message) -> Result<(), TransportError> {
+        for to in 0..self.inner.num_senders() {
+            self.recorder.record(Event::send(to, &message));
+        }
+        self.inner.send(message)
+    }
+
+    fn send_to(&self, to: usize, message: Message) -> Result<(), TransportError> {
+        self.recorder.record(Event::send(to, &message));
+        self.inner.send_to(to, message)
+    }
+
+    // bytes that don't decode never arrive as a message, so only the ones
+    // that do are sends
+    fn send_bytes(&self, to: usize, bytes: Vec<u8>) -> Result<(), TransportError> {
+        if let Ok(message) = Message::try_from(bytes.clone()) {
+            self.recorder.record(Event::send(to, &message));
+        }
+        self.inner.send_bytes(to, bytes)
+    }
+
+    fn send_to_self(&self, message: Message) -> Result<(), TransportError> {
+        self.inner.send_to_self(message)
+    }
+
+    fn receive(&self) -> Result<(usize, Message), TransportError> {
+        self.received(self.inner.receive())
+    }
+
+    fn receive_timeout(&self, timeout: Duration) -> Result<(usize, Message), TransportError> {
+        self.received(self.inner.receive_timeout(timeout))
+    }
+
+    fn poll_receive(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Poll<Result<(usize, Message), TransportError>> {
+        self.inner
+            .poll_receive(timeout)
+            .map(|received| self.received(received))
+    }
+
+    fn try_receive(&self) -> Result<Option<(usize, Message)>, TransportError> {
+        let received = self.inner.try_receive()?;
+        if let Some((from, message)) = &received {
+            self.recorder.record(Event::receive(*from, message));
+        }
+        Ok(received)
+    }
+
+    fn stopper(&self) -> Stopper {
+        self.inner.stopper()
+    }
+
+    fn num_malformed(&self) -> u64 {
+        self.inner.num_malformed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        scenario::{Scenario, TransportType},
+        step::Behavior,
+        termination::Termination,
+        transport::ByteChannel,
+    };
+
+    fn traced_run(transport: TransportType) -> Vec<TraceEvent> {
+        let mut scenario =
+            Scenario::for_simulation(4, 2, 1, &Behavior::RandomlyAdversarial, transport);
+        scenario.seed = Some(7);
+        scenario.termination = Termination {
+            max_phase: Some(3),
+            ..Termination::default()
+        };
+        let (run, trace) = scenario.run_traced().expect("start run");
+        run.for_each(drop);
+        trace.events()
+    }
+
+    #[test]
+    fn discrete_traces_are_reproducible() {
+        let events = traced_run(TransportType::DiscreteRandom);
+        assert!(!events.is_empty());
+        assert_eq!(events, traced_run(TransportType::DiscreteRandom));
+    }
+
+    #[test]
+    fn receives_follow_their_sends() {
+        let events = traced_run(TransportType::MessageChannel);
+        for (i, received) in events.iter().enumerate() {
+            if let Event::Receive { from, kind, value } = &received.event {
+                let send = Event::Send {
+                    to: received.process,
+                    kind: *kind,
+                    value: value.clone(),
+                };
+                assert!(events[..i].iter().any(|sent| sent.process == *from
+                    && sent.phase == received.phase
+                    && sent.event == send));
+            }
+        }
+    }
+
+    #[test]
+    fn bytes_that_decode_are_sends() {
+        let trace = Trace::new();
+        let mut channels = ByteChannel::new(2);
+        let transport = trace.recorder(0).wrap(channels.remove(0));
+        let report = Message::Report {
+            phase: Phase(1),
+            value: Value::One,
+        };
+        transport.send_bytes(1, report.into()).expect("send");
+        transport.send_bytes(1, vec![0xff]).expect("send");
+        assert_eq!(
+            trace.events(),
+            vec![TraceEvent {
+                process: 0,
+                phase: 1,
+                event: Event::Send {
+                    to: 1,
+                    kind: 'R',
+                    value: Some(Value::One)
+                }
+            }]
+        );
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let trace = Trace::new();
+        let recorder = trace.recorder(2);
+        recorder.record(Event::send(
+            0,
+            &Message::Proposal {
+                phase: Phase(1),
+                value: None,
+            },
+        ));
+        recorder.record((Phase(1), Event::Decide { value: Value::One }));
+        let mut jsonl = vec![];
+        trace.write_jsonl(&mut jsonl).expect("write");
+        assert_eq!(
+            TraceEvent::read_jsonl(&jsonl[..]).expect("read"),
+            vec![
+                TraceEvent {
+                    process: 2,
+                    phase: 1,
+                    event: Event::Send {
+                        to: 0,
+                        kind: 'P',
+                        value: None
+                    }
+                },
+                TraceEvent {
+                    process: 2,
+                    phase: 1,
+                    event: Event::Decide { value: Value::One }
+                }
+            ]
+        );
+        assert_eq!(
+            String::from_utf8(jsonl).expect("utf-8"),
+            "{\"process\":2,\"phase\":1,\"event\":\"send\",\"to\":0,\"kind\":\"P\",\"value\":null}\n\
+             {\"process\":2,\"phase\":1,\"event\":\"decide\",\"value\":1}\n"
+        );
+    }
+
+    // hands every flushed line to the test as it is written
+    struct Lines {
+        buffer: Vec<u8>,
+        lines: Sender<String>,
+    }
+
+    impl Write for Lines {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.buffer.extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            if !self.buffer.is_empty() {
+                let lines = String::from_utf8(self.buffer.split_off(0)).expect("utf-8");
+                let _ = self.lines.send(lines);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn streams_events_while_the_run_goes_on() {
+        let trace = Trace::new();
+        let recorder = trace.recorder(1);
+        let (sender, lines) = std::sync::mpsc::channel();
+        let writer = std::thread::spawn(move || {
+            trace.stream_jsonl(Lines {
+                buffer: vec![],
+                lines: sender,
+            })
+        });
+        recorder.record((Phase(3), Event::Decide { value: Value::Zero }));
+        assert_eq!(
+            lines
+                .recv_timeout(Duration::from_secs(5))
+                .expect("streamed"),
+            "{\"process\":1,\"phase\":3,\"event\":\"decide\",\"value\":0}\n"
+        );
+        drop(recorder);
+        writer.join().expect("writer").expect("write");
+    }
+}
@@ -1,14 +1,175 @@
+use std::{
+    convert::TryFrom,
+    error, fmt,
+    sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError},
+    task::Poll,
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
 use crate::message::Message;
 
 mod byte_channel;
+mod faulty_transport;
 mod message_channel;
+mod partitioned_transport;
+mod tcp_transport;
 
 pub use byte_channel::ByteChannel;
+pub use faulty_transport::{Faults, FaultyTransport, Latency};
 pub use message_channel::MessageChannel;
+pub use partitioned_transport::{
+    CrossTraffic, Partition, PartitionSchedule, PartitionedTransport, Partitions,
+};
+pub use tcp_transport::TcpTransport;
+pub(crate) use tcp_transport::MAX_FRAME_LEN;
+
+// how long `receive_timeout` sleeps between polls by default
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Stops a transport from another thread, see `Transport::stopper`.
+pub type Stopper = Box<dyn Fn() + Send>;
 
 pub trait Transport: Send {
     fn num_senders(&self) -> usize;
-    fn send(&self, message: Message);
-    fn send_to_self(&self, message: Message);
-    fn receive(&self) -> Message;
+    /// Sends the message to every process. A recipient that's gone has
+    /// crashed, which the algorithm tolerates, so this only fails if the
+    /// sender can't even reach itself.
+    fn send(&self, message: Message) -> Result<(), TransportError>;
+    fn send_to(&self, to: usize, message: Message) -> Result<(), TransportError>;
+    fn send_to_self(&self, message: Message) -> Result<(), TransportError>;
+    /// Returns the next message along with the id of its sender, which the
+    /// transport vouches for, so a process can't pass itself off as another.
+    fn receive(&self) -> Result<(usize, Message), TransportError>;
+    /// Like `receive`, but returns `None` instead of waiting.
+    fn try_receive(&self) -> Result<Option<(usize, Message)>, TransportError>;
+    /// Returns a way for another thread to stop this transport: the
+    /// `receive` waiting at the time, or else the next one, fails with
+    /// `TransportError::Stopped`.
+    fn stopper(&self) -> Stopper;
+
+    /// Like `receive`, but fails with `TransportError::Timeout` if nothing
+    /// arrives in time.
+    fn receive_timeout(&self, timeout: Duration) -> Result<(usize, Message), TransportError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(received) = self.try_receive()? {
+                return Ok(received);
+            }
+            if Instant::now() >= deadline {
+                return Err(TransportError::Timeout);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Like `receive`, or `receive_timeout` given a timeout, for a process
+    /// that a scheduler drives instead of a thread of its own: a transport
+    /// that can't block returns `Poll::Pending` while nothing has arrived,
+    /// and its process is polled again once something has.
+    fn poll_receive(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Poll<Result<(usize, Message), TransportError>> {
+        Poll::Ready(match timeout {
+            Some(timeout) => self.receive_timeout(timeout),
+            None => self.receive(),
+        })
+    }
+
+    /// Sends bytes that may or may not encode a message. Transports that
+    /// carry bytes deliver them as they are, and the others decode them
+    /// first, failing if they're malformed.
+    fn send_bytes(&self, to: usize, bytes: Vec<u8>) -> Result<(), TransportError> {
+        let message =
+            Message::try_from(bytes).map_err(|e| TransportError::Decode(e.to_string()))?;
+        self.send_to(to, message)
+    }
+
+    /// The number of frames received so far that couldn't be decoded and
+    /// were dropped.
+    fn num_malformed(&self) -> u64 {
+        0
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportError {
+    /// The other end of a channel or connection is gone.
+    Disconnected,
+    Decode(String),
+    Timeout,
+    /// There's no process with this id to send to.
+    NoSuchProcess(usize),
+    /// The transport was stopped while its process was still running.
+    Stopped,
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Disconnected => write!(f, "disconnected"),
+            TransportError::Decode(e) => write!(f, "failed to decode: {}", e),
+            TransportError::Timeout => write!(f, "timed out"),
+            TransportError::NoSuchProcess(id) => write!(f, "no process {}", id),
+            TransportError::Stopped => write!(f, "stopped"),
+        }
+    }
+}
+
+impl error::Error for TransportError {}
+
+// what the channel of a transport carries: a message or bytes along with
+// their sender, or the error that its receiver is to fail with
+pub(crate) type Delivery<T> = Result<(usize, T), TransportError>;
+
+pub(crate) fn recv<T>(receiver: &Receiver<Delivery<T>>) -> Delivery<T> {
+    receiver.recv().unwrap_or(Err(TransportError::Disconnected))
+}
+
+pub(crate) fn recv_timeout<T>(receiver: &Receiver<Delivery<T>>, timeout: Duration) -> Delivery<T> {
+    match receiver.recv_timeout(timeout) {
+        Ok(delivery) => delivery,
+        Err(RecvTimeoutError::Timeout) => Err(TransportError::Timeout),
+        Err(RecvTimeoutError::Disconnected) => Err(TransportError::Disconnected),
+    }
+}
+
+pub(crate) fn try_recv<T>(
+    receiver: &Receiver<Delivery<T>>,
+) -> Result<Option<(usize, T)>, TransportError> {
+    match receiver.try_recv() {
+        Ok(delivery) => delivery.map(Some),
+        Err(TryRecvError::Empty) => Ok(None),
+        Err(TryRecvError::Disconnected) => Err(TransportError::Disconnected),
+    }
+}
+
+// stops a transport by waking up its receiver with an error
+pub(crate) fn stopper<T: Send + 'static>(sender: &Sender<Delivery<T>>) -> Stopper {
+    let sender = sender.clone();
+    Box::new(move || {
+        // a receiver that's gone has nothing left to stop
+        let _ = sender.send(Err(TransportError::Stopped));
+    })
+}
+
+// sends to every process with `send_to`, as `Transport::send` does
+pub(crate) fn broadcast(
+    id: usize,
+    num_processes: usize,
+    send_to: impl Fn(usize) -> Result<(), TransportError>,
+) -> Result<(), TransportError> {
+    for to in 0..num_processes {
+        match send_to(to) {
+            Err(e) if to == id => return Err(e),
+            Err(e) => warn!(to, error = %e, "failed to send"),
+            Ok(()) => {}
+        }
+    }
+    Ok(())
 }
@@ -1,17 +1,27 @@
 use std::{
-    convert::TryInto,
+    cell::Cell,
+    convert::TryFrom,
     sync::mpsc::{Receiver, Sender},
+    time::{Duration, Instant},
 };
 
-use crate::{message::Message, transport::Transport};
+use tracing::warn;
+
+use crate::{
+    message::Message,
+    transport::{self, broadcast, Delivery, Stopper, Transport, TransportError},
+};
 
 pub struct ByteChannel {
-    self_sender: Sender<Vec<u8>>,
-    senders: Vec<Sender<Vec<u8>>>,
-    receiver: Receiver<Vec<u8>>,
+    id: usize,
+    self_sender: Sender<Delivery<Vec<u8>>>,
+    senders: Vec<Sender<Delivery<Vec<u8>>>>,
+    receiver: Receiver<Delivery<Vec<u8>>>,
+    num_malformed: Cell<u64>,
 }
 
 impl ByteChannel {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(num_processes: usize) -> Vec<Box<dyn Transport>> {
         let mut senders = vec![];
         let mut receivers = vec![];
@@ -25,9 +35,11 @@ impl ByteChannel {
             .into_iter()
             .enumerate()
             .map(|(i, receiver)| ByteChannel {
+                id: i,
                 self_sender: senders[i].clone(),
                 senders: senders.clone(),
                 receiver,
+                num_malformed: Cell::new(0),
             })
         {
             let b: Box<dyn Transport> = Box::new(receiver);
@@ -37,32 +49,143 @@ impl ByteChannel {
     }
 }
 
+impl ByteChannel {
+    fn decode(&self, from: usize, bytes: Vec<u8>) -> Option<Message> {
+        Message::try_from(bytes)
+            .map_err(|e| {
+                warn!(from, error = e, "dropped a malformed frame");
+                self.num_malformed.set(self.num_malformed.get() + 1);
+            })
+            .ok()
+    }
+}
+
 impl Transport for ByteChannel {
     fn num_senders(&self) -> usize {
         self.senders.len()
     }
 
-    fn send(&self, message: Message) {
-        for sender in &self.senders {
-            let _ = sender
-                .send(message.clone().into())
-                .map_err(|e| eprintln!("Failed to send {:?}", e.0));
+    fn send(&self, message: Message) -> Result<(), TransportError> {
+        broadcast(self.id, self.senders.len(), |to| {
+            self.send_to(to, message.clone())
+        })
+    }
+
+    fn send_to(&self, to: usize, message: Message) -> Result<(), TransportError> {
+        self.send_bytes(to, message.into())
+    }
+
+    fn send_to_self(&self, message: Message) -> Result<(), TransportError> {
+        self.self_sender
+            .send(Ok((self.id, message.into())))
+            .map_err(|_| TransportError::Disconnected)
+    }
+
+    fn send_bytes(&self, to: usize, bytes: Vec<u8>) -> Result<(), TransportError> {
+        self.senders
+            .get(to)
+            .ok_or(TransportError::NoSuchProcess(to))?
+            .send(Ok((self.id, bytes)))
+            .map_err(|_| TransportError::Disconnected)
+    }
+
+    fn receive(&self) -> Result<(usize, Message), TransportError> {
+        loop {
+            let (from, bytes) = transport::recv(&self.receiver)?;
+            if let Some(message) = self.decode(from, bytes) {
+                return Ok((from, message));
+            }
         }
     }
 
-    fn send_to_self(&self, message: Message) {
-        let _ = self
-            .self_sender
-            .send(message.clone().into())
-            .map_err(|e| eprintln!("Failed to send to self {:?}", e.0));
+    fn receive_timeout(&self, timeout: Duration) -> Result<(usize, Message), TransportError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let (from, bytes) = transport::recv_timeout(&self.receiver, timeout)?;
+            if let Some(message) = self.decode(from, bytes) {
+                return Ok((from, message));
+            }
+        }
+    }
+
+    fn try_receive(&self) -> Result<Option<(usize, Message)>, TransportError> {
+        loop {
+            let (from, bytes) = match transport::try_recv(&self.receiver)? {
+                Some(received) => received,
+                None => return Ok(None),
+            };
+            if let Some(message) = self.decode(from, bytes) {
+                return Ok(Some((from, message)));
+            }
+        }
+    }
+
+    fn stopper(&self) -> Stopper {
+        transport::stopper(&self.self_sender)
+    }
+
+    fn num_malformed(&self) -> u64 {
+        self.num_malformed.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        batch::Batch,
+        coin::CoinType,
+        message::{Phase, Value},
+        step::Behavior,
+    };
+
+    #[test]
+    fn drops_malformed_frames() {
+        let transports = ByteChannel::new(2);
+        let message = Message::Report {
+            phase: Phase(1),
+            value: Value::Zero,
+        };
+        transports[1].send_bytes(0, vec![2, 0xff]).expect("send");
+        transports[1].send_to(0, message.clone()).expect("send");
+        transports[1].send_bytes(0, vec![]).expect("send");
+        assert_eq!(transports[0].receive(), Ok((1, message)));
+        assert_eq!(transports[0].try_receive(), Ok(None));
+        assert_eq!(transports[0].num_malformed(), 2);
+    }
+
+    #[test]
+    fn sends_to_no_process_fail() {
+        let transports = ByteChannel::new(2);
+        let message = Message::Report {
+            phase: Phase(1),
+            value: Value::Zero,
+        };
+        assert_eq!(
+            transports[0].send_to(2, message),
+            Err(TransportError::NoSuchProcess(2))
+        );
+        assert_eq!(
+            transports[0].send_bytes(3, vec![]),
+            Err(TransportError::NoSuchProcess(3))
+        );
     }
 
-    fn receive(&self) -> Message {
-        self.receiver
-            .recv()
-            .expect("recv")
-            .try_into()
-            .map_err(|e| eprintln!("Failed to parse: {}", e))
-            .expect("parse")
+    #[test]
+    fn correct_processes_outlast_random_bytes() {
+        let batch = Batch {
+            num_processes: 5,
+            num_zeros: 2,
+            num_adversaries: 1,
+            behavior: Behavior::SendsRandomBytes,
+            coin: CoinType::Local,
+            runs: 5,
+            max_phase: 50,
+            seed: 0,
+        };
+        let report = batch.run(ByteChannel::new);
+        assert_eq!(report.unterminated, 0);
     }
 }
@@ -0,0 +1,489 @@
+use std::{
+    cell::RefCell,
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, VecDeque},
+    thread,
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::{
+    message::Message,
+    transport::{Stopper, Transport, TransportError},
+};
+
+// how long to sleep between polls while a delayed message is not due yet
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// How long a message spends on a link. In a config file the durations are
+/// in milliseconds, e.g. `latency = { uniform = [1, 5] }`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Latency {
+    Constant(#[serde(with = "millis")] Duration),
+    Uniform(
+        #[serde(with = "millis")] Duration,
+        #[serde(with = "millis")] Duration,
+    ),
+    Exponential(#[serde(with = "millis")] Duration),
+}
+
+impl Latency {
+    fn sample(&self, rng: &mut StdRng) -> Duration {
+        match self {
+            Latency::Constant(latency) => *latency,
+            Latency::Uniform(min, max) => {
+                if min >= max {
+                    *min
+                } else {
+                    *min + (*max - *min).mul_f64(rng.gen::<f64>())
+                }
+            }
+            Latency::Exponential(mean) => mean.mul_f64(-(1.0 - rng.gen::<f64>()).ln()),
+        }
+    }
+}
+
+/// The faults injected on the link from one process to another.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Faults {
+    pub latency: Latency,
+    pub drop_probability: f64,
+    pub duplicate_probability: f64,
+    /// Probability that a message is held back for an extra `reorder_delay`,
+    /// letting the messages behind it overtake it.
+    pub reorder_probability: f64,
+    #[serde(with = "millis")]
+    pub reorder_delay: Duration,
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Faults {
+            latency: Latency::Constant(Duration::from_millis(0)),
+            drop_probability: 0.0,
+            duplicate_probability: 0.0,
+            reorder_probability: 0.0,
+            reorder_delay: Duration::from_millis(0),
+        }
+    }
+}
+
+impl Faults {
+    /// Fails unless every probability is between 0 and 1.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        let probabilities = [
+            self.drop_probability,
+            self.duplicate_probability,
+            self.reorder_probability,
+        ];
+        if probabilities
+            .iter()
+            .all(|probability| (0.0..=1.0).contains(probability))
+        {
+            Ok(())
+        } else {
+            Err("fault probabilities must be between 0 and 1")
+        }
+    }
+}
+
+// durations as a whole number of milliseconds
+mod millis {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(
+        duration: &Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+/// Decorates a transport with a lossy, asynchronous network.
+///
+/// The faults are applied to the messages as they come out of the inner
+/// transport. Messages a process re-queues for itself with `send_to_self`
+/// never touch the network, so they are not subject to faults.
+pub struct FaultyTransport {
+    id: usize,
+    inner: Box<dyn Transport>,
+    // by sender
+    links: Vec<Faults>,
+    rng: RefCell<StdRng>,
+    num_received: RefCell<u64>,
+    in_flight: RefCell<BinaryHeap<Reverse<InFlight>>>,
+    requeued: RefCell<VecDeque<Message>>,
+}
+
+impl FaultyTransport {
+    /// Decorates the transport of process `id`, with the same faults on the
+    /// links from every process. Fails if the faults aren't valid.
+    pub fn new(
+        id: usize,
+        inner: Box<dyn Transport>,
+        faults: Faults,
+        seed: u64,
+    ) -> Result<Self, &'static str> {
+        faults.validate()?;
+        let links = vec![faults; inner.num_senders()];
+        Ok(Self::with_links(id, inner, links, seed))
+    }
+
+    fn with_links(id: usize, inner: Box<dyn Transport>, links: Vec<Faults>, seed: u64) -> Self {
+        FaultyTransport {
+            id,
+            inner,
+            links,
+            rng: RefCell::new(StdRng::seed_from_u64(seed)),
+            num_received: RefCell::new(0),
+            in_flight: RefCell::new(BinaryHeap::new()),
+            requeued: RefCell::new(VecDeque::new()),
+        }
+    }
+
+    /// Decorates every transport, with the faults on the link from process
+    /// `from` to process `to` given by `faults(from, to)`. Fails if the
+    /// faults of any link aren't valid.
+    pub fn wrap(
+        transports: Vec<Box<dyn Transport>>,
+        faults: impl Fn(usize, usize) -> Faults,
+        seed: u64,
+    ) -> Result<Vec<Box<dyn Transport>>, &'static str> {
+        let mut master = StdRng::seed_from_u64(seed);
+        let mut boxes = vec![];
+        for (i, transport) in transports.into_iter().enumerate() {
+            let links = (0..transport.num_senders())
+                .map(|from| {
+                    let faults = faults(from, i);
+                    faults.validate().map(|()| faults)
+                })
+                .collect::<Result<_, _>>()?;
+            let b: Box<dyn Transport> = Box::new(FaultyTransport::with_links(
+                i,
+                transport,
+                links,
+                master.gen(),
+            ));
+            boxes.push(b);
+        }
+        Ok(boxes)
+    }
+
+    fn admit(&self, (from, message): (usize, Message)) {
+        let faults = &self.links[from];
+        let mut rng = self.rng.borrow_mut();
+        if rng.gen_bool(faults.drop_probability) {
+            debug!(from, ?message, "network dropped a message");
+            return;
+        }
+        let copies = if rng.gen_bool(faults.duplicate_probability) {
+            debug!(from, ?message, "network duplicated a message");
+            2
+        } else {
+            1
+        };
+        let now = Instant::now();
+        for _ in 0..copies {
+            let mut latency = faults.latency.sample(&mut rng);
+            if rng.gen_bool(faults.reorder_probability) {
+                latency += faults.reorder_delay;
+            }
+            let mut num_received = self.num_received.borrow_mut();
+            self.in_flight.borrow_mut().push(Reverse(InFlight {
+                due: now + latency,
+                seq: *num_received,
+                from,
+                message: message.clone(),
+            }));
+            *num_received += 1;
+        }
+    }
+
+    fn admit_all(&self) -> Result<(), TransportError> {
+        while let Some(received) = self.inner.try_receive()? {
+            self.admit(received);
+        }
+        Ok(())
+    }
+
+    fn receive_until(&self, deadline: Option<Instant>) -> Result<(usize, Message), TransportError> {
+        loop {
+            if let Some(received) = self.try_receive()? {
+                return Ok(received);
+            }
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if remaining == Some(Duration::from_millis(0)) {
+                return Err(TransportError::Timeout);
+            }
+            let next_due = self.in_flight.borrow().peek().map(|Reverse(next)| next.due);
+            match (next_due, remaining) {
+                (Some(due), _) => thread::sleep(std::cmp::min(
+                    due.saturating_duration_since(Instant::now()),
+                    POLL_INTERVAL,
+                )),
+                (None, None) => {
+                    let received = self.inner.receive()?;
+                    self.admit(received);
+                }
+                (None, Some(remaining)) => {
+                    let received = self.inner.receive_timeout(remaining)?;
+                    self.admit(received);
+                }
+            }
+        }
+    }
+
+    fn pop_due(&self) -> Option<(usize, Message)> {
+        let mut in_flight = self.in_flight.borrow_mut();
+        match in_flight.peek() {
+            Some(Reverse(next)) if next.due <= Instant::now() => in_flight
+                .pop()
+                .map(|Reverse(next)| (next.from, next.message)),
+            _ => None,
+        }
+    }
+}
+
+impl Transport for FaultyTransport {
+    fn num_senders(&self) -> usize {
+        self.inner.num_senders()
+    }
+
+    fn send(&self, message: Message) -> Result<(), TransportError> {
+        self.inner.send(message)
+    }
+
+    fn send_to(&self, to: usize, message: Message) -> Result<(), TransportError> {
+        self.inner.send_to(to, message)
+    }
+
+    fn send_bytes(&self, to: usize, bytes: Vec<u8>) -> Result<(), TransportError> {
+        self.inner.send_bytes(to, bytes)
+    }
+
+    fn send_to_self(&self, message: Message) -> Result<(), TransportError> {
+        self.requeued.borrow_mut().push_back(message);
+        Ok(())
+    }
+
+    fn receive(&self) -> Result<(usize, Message), TransportError> {
+        self.receive_until(None)
+    }
+
+    fn receive_timeout(&self, timeout: Duration) -> Result<(usize, Message), TransportError> {
+        self.receive_until(Some(Instant::now() + timeout))
+    }
+
+    fn try_receive(&self) -> Result<Option<(usize, Message)>, TransportError> {
+        self.admit_all()?;
+        Ok(self.pop_due().or_else(|| {
+            self.requeued
+                .borrow_mut()
+                .pop_front()
+                .map(|message| (self.id, message))
+        }))
+    }
+
+    fn stopper(&self) -> Stopper {
+        self.inner.stopper()
+    }
+
+    fn num_malformed(&self) -> u64 {
+        self.inner.num_malformed()
+    }
+}
+
+struct InFlight {
+    due: Instant,
+    seq: u64,
+    from: usize,
+    message: Message,
+}
+
+impl PartialEq for InFlight {
+    fn eq(&self, other: &Self) -> bool {
+        (self.due, self.seq) == (other.due, other.seq)
+    }
+}
+
+impl Eq for InFlight {}
+
+impl PartialOrd for InFlight {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for InFlight {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.due, self.seq).cmp(&(other.due, other.seq))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        message::{Phase, Value},
+        transport::MessageChannel,
+    };
+
+    fn report(phase: u64) -> Message {
+        Message::Report {
+            phase: Phase(phase),
+            value: Value::One,
+        }
+    }
+
+    fn faulty(faults: Faults) -> Box<dyn Transport> {
+        FaultyTransport::wrap(MessageChannel::new(1), |_, _| faults.clone(), 0)
+            .expect("valid faults")
+            .remove(0)
+    }
+
+    #[test]
+    fn drops() {
+        let transport = faulty(Faults {
+            drop_probability: 1.0,
+            ..Faults::default()
+        });
+        transport.send(report(0)).expect("send");
+        assert_eq!(transport.try_receive(), Ok(None));
+    }
+
+    #[test]
+    fn duplicates() {
+        let transport = faulty(Faults {
+            duplicate_probability: 1.0,
+            ..Faults::default()
+        });
+        transport.send(report(0)).expect("send");
+        assert_eq!(transport.receive(), Ok((0, report(0))));
+        assert_eq!(transport.receive(), Ok((0, report(0))));
+        assert_eq!(transport.try_receive(), Ok(None));
+    }
+
+    #[test]
+    fn delays() {
+        let latency = Duration::from_millis(20);
+        let transport = faulty(Faults {
+            latency: Latency::Constant(latency),
+            ..Faults::default()
+        });
+        let start = Instant::now();
+        transport.send(report(0)).expect("send");
+        assert_eq!(transport.try_receive(), Ok(None));
+        assert_eq!(transport.receive(), Ok((0, report(0))));
+        assert!(start.elapsed() >= latency);
+    }
+
+    #[test]
+    fn times_out_before_a_delayed_message_is_due() {
+        let transport = faulty(Faults {
+            latency: Latency::Constant(Duration::from_millis(50)),
+            ..Faults::default()
+        });
+        transport.send(report(0)).expect("send");
+        assert_eq!(
+            transport.receive_timeout(Duration::from_millis(5)),
+            Err(TransportError::Timeout)
+        );
+        assert_eq!(
+            transport.receive_timeout(Duration::from_secs(5)),
+            Ok((0, report(0)))
+        );
+    }
+
+    #[test]
+    fn reorders() {
+        let transport = faulty(Faults {
+            latency: Latency::Uniform(Duration::from_millis(0), Duration::from_millis(10)),
+            reorder_probability: 0.5,
+            reorder_delay: Duration::from_millis(20),
+            ..Faults::default()
+        });
+        for phase in 0..10 {
+            transport.send(report(phase)).expect("send");
+        }
+        let mut received = (0..10)
+            .map(|_| transport.receive().expect("receive").1)
+            .collect::<Vec<_>>();
+        let sent = (0..10).map(report).collect::<Vec<_>>();
+        assert_ne!(received, sent);
+        received.sort_by(|a, b| match (a, b) {
+            (Message::Report { phase: a, .. }, Message::Report { phase: b, .. }) => {
+                a.partial_cmp(b).expect("phases are comparable")
+            }
+            _ => unreachable!(),
+        });
+        assert_eq!(received, sent);
+    }
+
+    #[test]
+    fn invalid_probabilities_are_rejected() {
+        for probability in [-0.1, 1.5, f64::NAN] {
+            let faults = Faults {
+                duplicate_probability: probability,
+                ..Faults::default()
+            };
+            assert!(
+                FaultyTransport::new(0, MessageChannel::new(1).remove(0), faults.clone(), 0)
+                    .is_err()
+            );
+            assert!(FaultyTransport::wrap(
+                MessageChannel::new(2),
+                |_, to| if to == 1 {
+                    faults.clone()
+                } else {
+                    Faults::default()
+                },
+                0
+            )
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn faults_apply_to_the_link_from_one_process_to_another() {
+        let transports = FaultyTransport::wrap(
+            MessageChannel::new(3),
+            |from, to| Faults {
+                drop_probability: if (from, to) == (1, 0) { 1.0 } else { 0.0 },
+                ..Faults::default()
+            },
+            0,
+        )
+        .expect("valid faults");
+        transports[1].send_to(0, report(0)).expect("send");
+        transports[2].send_to(0, report(1)).expect("send");
+        transports[1].send_to(2, report(2)).expect("send");
+        assert_eq!(transports[0].receive(), Ok((2, report(1))));
+        assert_eq!(transports[0].try_receive(), Ok(None));
+        assert_eq!(transports[2].receive(), Ok((1, report(2))));
+    }
+
+    #[test]
+    fn requeued_messages_are_not_faulty() {
+        let transport = faulty(Faults {
+            drop_probability: 1.0,
+            ..Faults::default()
+        });
+        transport.send_to_self(report(1)).expect("send");
+        assert_eq!(transport.receive(), Ok((0, report(1))));
+    }
+}
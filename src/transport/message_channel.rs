@@ -1,14 +1,22 @@
-use std::sync::mpsc::{Receiver, Sender};
+use std::{
+    sync::mpsc::{Receiver, Sender},
+    time::Duration,
+};
 
-use crate::{message::Message, transport::Transport};
+use crate::{
+    message::Message,
+    transport::{self, broadcast, Delivery, Stopper, Transport, TransportError},
+};
 
 pub struct MessageChannel {
-    self_sender: Sender<Message>,
-    senders: Vec<Sender<Message>>,
-    receiver: Receiver<Message>,
+    id: usize,
+    self_sender: Sender<Delivery<Message>>,
+    senders: Vec<Sender<Delivery<Message>>>,
+    receiver: Receiver<Delivery<Message>>,
 }
 
 impl MessageChannel {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(num_processes: usize) -> Vec<Box<dyn Transport>> {
         let mut senders = vec![];
         let mut receivers = vec![];
@@ -22,6 +30,7 @@ impl MessageChannel {
             .into_iter()
             .enumerate()
             .map(|(i, receiver)| MessageChannel {
+                id: i,
                 self_sender: senders[i].clone(),
                 senders: senders.clone(),
                 receiver,
@@ -39,22 +48,67 @@ impl Transport for MessageChannel {
         self.senders.len()
     }
 
-    fn send(&self, message: Message) {
-        for sender in &self.senders {
-            let _ = sender
-                .send(Message::from(message.clone()))
-                .map_err(|e| eprintln!("Failed to send {:?}", e.0));
-        }
+    fn send(&self, message: Message) -> Result<(), TransportError> {
+        broadcast(self.id, self.senders.len(), |to| {
+            self.send_to(to, message.clone())
+        })
+    }
+
+    fn send_to(&self, to: usize, message: Message) -> Result<(), TransportError> {
+        self.senders
+            .get(to)
+            .ok_or(TransportError::NoSuchProcess(to))?
+            .send(Ok((self.id, message)))
+            .map_err(|_| TransportError::Disconnected)
+    }
+
+    fn send_to_self(&self, message: Message) -> Result<(), TransportError> {
+        self.self_sender
+            .send(Ok((self.id, message)))
+            .map_err(|_| TransportError::Disconnected)
+    }
+
+    fn receive(&self) -> Result<(usize, Message), TransportError> {
+        transport::recv(&self.receiver)
+    }
+
+    fn receive_timeout(&self, timeout: Duration) -> Result<(usize, Message), TransportError> {
+        transport::recv_timeout(&self.receiver, timeout)
+    }
+
+    fn try_receive(&self) -> Result<Option<(usize, Message)>, TransportError> {
+        transport::try_recv(&self.receiver)
+    }
+
+    fn stopper(&self) -> Stopper {
+        transport::stopper(&self.self_sender)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::message::{Phase, Value};
 
-    fn send_to_self(&self, message: Message) {
-        let _ = self
-            .self_sender
-            .send(Message::from(message.clone()))
-            .map_err(|e| eprintln!("Failed to send to self {:?}", e.0));
+    #[test]
+    fn sends_to_no_process_fail() {
+        let transports = MessageChannel::new(2);
+        let message = Message::Report {
+            phase: Phase(1),
+            value: Value::Zero,
+        };
+        assert_eq!(
+            transports[0].send_to(2, message),
+            Err(TransportError::NoSuchProcess(2))
+        );
     }
 
-    fn receive(&self) -> Message {
-        self.receiver.recv().expect("recv").into()
+    #[test]
+    fn stopping_wakes_up_a_receive() {
+        let transports = MessageChannel::new(2);
+        let stop = transports[0].stopper();
+        std::thread::spawn(stop);
+        assert_eq!(transports[0].receive(), Err(TransportError::Stopped));
     }
 }
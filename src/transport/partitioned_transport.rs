@@ -0,0 +1,604 @@
+use std::{
+    collections::VecDeque,
+    convert::TryFrom,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::{
+    message::{Message, Phase},
+    transport::{Stopper, Transport, TransportError},
+};
+
+// how long to sleep between polls of the inner transport and the inbox
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Splits the processes into groups for the messages of phases `from` to
+/// `to`, both inclusive. Processes that are in none of the groups are cut
+/// off from everyone else.
+///
+/// Parses from strings like `0..4|5..10@3..7`, which is also how it reads
+/// in a config file.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Partition {
+    pub groups: Vec<Vec<usize>>,
+    pub from: Phase,
+    pub to: Phase,
+}
+
+impl Partition {
+    fn group_of(&self, id: usize) -> Option<usize> {
+        self.groups.iter().position(|group| group.contains(&id))
+    }
+
+    fn separates(&self, a: usize, b: usize) -> bool {
+        a != b && (self.group_of(a).is_none() || self.group_of(a) != self.group_of(b))
+    }
+
+    fn covers(&self, phase: Phase) -> bool {
+        self.from <= phase && phase <= self.to
+    }
+}
+
+impl fmt::Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let groups = self
+            .groups
+            .iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .map(usize::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect::<Vec<_>>()
+            .join("|");
+        write!(f, "{}@{}..{}", groups, self.from.0, self.to.0)
+    }
+}
+
+impl FromStr for Partition {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '@');
+        let groups = parts.next().ok_or("missing groups")?;
+        let (from, to) = parse_range(parts.next().ok_or("missing phases")?)?;
+        let groups = groups
+            .split('|')
+            .map(|group| {
+                let mut ids = vec![];
+                for ids_or_range in group.split(',') {
+                    let (first, last) = parse_range(ids_or_range)?;
+                    ids.extend(first as usize..=last as usize);
+                }
+                Ok(ids)
+            })
+            .collect::<Result<Vec<_>, Self::Err>>()?;
+        if from > to {
+            return Err("partition ends before it starts");
+        }
+        Ok(Partition {
+            groups,
+            from: Phase(from),
+            to: Phase(to),
+        })
+    }
+}
+
+impl TryFrom<String> for Partition {
+    type Error = &'static str;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Partition> for String {
+    fn from(partition: Partition) -> String {
+        partition.to_string()
+    }
+}
+
+// either a single number or an inclusive range like `3..7`
+fn parse_range(s: &str) -> Result<(u64, u64), &'static str> {
+    let parse = |s: &str| s.trim().parse().map_err(|_| "not a number");
+    let mut bounds = s.splitn(2, "..");
+    let first = parse(bounds.next().ok_or("empty range")?)?;
+    let last = match bounds.next() {
+        Some(last) => parse(last)?,
+        None => first,
+    };
+    Ok((first, last))
+}
+
+/// What happens to the messages that cross a partition.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CrossTraffic {
+    /// Deliver them once the partition heals.
+    #[default]
+    Buffer,
+    Drop,
+}
+
+impl FromStr for CrossTraffic {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "buffer" => Ok(CrossTraffic::Buffer),
+            "drop" => Ok(CrossTraffic::Drop),
+            _ => Err("invalid cross traffic string"),
+        }
+    }
+}
+
+/// The partitions of a run, as in a config file:
+///
+/// ```toml
+/// [partitions]
+/// schedule = ["0,1|2..4@1..3"]
+/// cross_traffic = "drop"
+/// heal_after_ms = 500
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PartitionSchedule {
+    pub schedule: Vec<Partition>,
+    #[serde(default)]
+    pub cross_traffic: CrossTraffic,
+    /// How long a partition lasts at most, see `PartitionedTransport`.
+    #[serde(default = "default_heal_after_ms")]
+    pub heal_after_ms: u64,
+}
+
+fn default_heal_after_ms() -> u64 {
+    PartitionSchedule::DEFAULT_HEAL_AFTER_MS
+}
+
+impl PartitionSchedule {
+    pub const DEFAULT_HEAL_AFTER_MS: u64 = 1000;
+
+    /// Fails if a partition puts a process that doesn't exist in a group.
+    pub fn validate(&self, num_processes: usize) -> Result<(), &'static str> {
+        if self
+            .schedule
+            .iter()
+            .flat_map(|partition| partition.groups.iter().flatten())
+            .all(|id| *id < num_processes)
+        {
+            Ok(())
+        } else {
+            Err("partitions must only group processes that exist")
+        }
+    }
+
+    /// Like `PartitionedTransport::wrap`.
+    pub fn wrap(
+        &self,
+        transports: Vec<Box<dyn Transport>>,
+    ) -> (Vec<Box<dyn Transport>>, Partitions) {
+        PartitionedTransport::wrap(
+            transports,
+            self.schedule.clone(),
+            self.cross_traffic,
+            Duration::from_millis(self.heal_after_ms),
+        )
+    }
+}
+
+// the time as the network sees it, which tests make up
+type Clock = Box<dyn Fn() -> Instant + Send + Sync>;
+
+/// Splits the network according to a schedule of partitions.
+///
+/// The schedule is driven by the phases in the messages: a partition is in
+/// effect for the messages of its phases until some process sends a message
+/// of a later phase, at which point it heals and the buffered cross traffic
+/// is delivered. Since no process may get that far, say when no side has
+/// n - f processes, a partition also heals once it has been in effect for
+/// `heal_after`.
+pub struct PartitionedTransport {
+    id: usize,
+    inner: Box<dyn Transport>,
+    network: Arc<Network>,
+}
+
+/// A handle on the network of a set of `PartitionedTransport`s, to find out
+/// how far each side of each partition got.
+#[derive(Clone)]
+pub struct Partitions {
+    network: Arc<Network>,
+}
+
+struct Network {
+    schedule: Vec<Partition>,
+    cross_traffic: CrossTraffic,
+    heal_after: Duration,
+    clock: Clock,
+    // (sender, message) for each recipient
+    inboxes: Vec<Mutex<VecDeque<(usize, Message)>>>,
+    state: Mutex<State>,
+}
+
+struct State {
+    healed: Vec<bool>,
+    // when each partition first held a message apart
+    started: Vec<Option<Instant>>,
+    // messages held back by a partition: (partition, sender, recipient, message)
+    held: Vec<(usize, usize, usize, Message)>,
+    // for each partition and group, the last phase sent while partitioned
+    progress: Vec<Vec<Option<Phase>>>,
+}
+
+impl PartitionedTransport {
+    pub fn wrap(
+        transports: Vec<Box<dyn Transport>>,
+        schedule: Vec<Partition>,
+        cross_traffic: CrossTraffic,
+        heal_after: Duration,
+    ) -> (Vec<Box<dyn Transport>>, Partitions) {
+        Self::wrap_with_clock(
+            transports,
+            schedule,
+            cross_traffic,
+            heal_after,
+            Box::new(Instant::now),
+        )
+    }
+
+    fn wrap_with_clock(
+        transports: Vec<Box<dyn Transport>>,
+        schedule: Vec<Partition>,
+        cross_traffic: CrossTraffic,
+        heal_after: Duration,
+        clock: Clock,
+    ) -> (Vec<Box<dyn Transport>>, Partitions) {
+        let network = Arc::new(Network {
+            inboxes: transports
+                .iter()
+                .map(|_| Mutex::new(VecDeque::new()))
+                .collect(),
+            state: Mutex::new(State {
+                healed: vec![false; schedule.len()],
+                started: vec![None; schedule.len()],
+                held: vec![],
+                progress: schedule
+                    .iter()
+                    .map(|partition| vec![None; partition.groups.len()])
+                    .collect(),
+            }),
+            schedule,
+            cross_traffic,
+            heal_after,
+            clock,
+        });
+        let mut boxes = vec![];
+        for (id, inner) in transports.into_iter().enumerate() {
+            let b: Box<dyn Transport> = Box::new(PartitionedTransport {
+                id,
+                inner,
+                network: network.clone(),
+            });
+            boxes.push(b);
+        }
+        (boxes, Partitions { network })
+    }
+}
+
+impl Partitions {
+    /// For each partition in the schedule, and each of its groups, the last
+    /// phase a member of the group sent a message for while partitioned.
+    pub fn progress(&self) -> Vec<(Partition, Vec<Option<Phase>>)> {
+        let state = self.network.state.lock().expect("lock");
+        self.network
+            .schedule
+            .iter()
+            .cloned()
+            .zip(state.progress.iter().cloned())
+            .collect()
+    }
+}
+
+impl PartitionedTransport {
+    // sends to `to`, or to everyone if it's `None`
+    fn route(&self, message: Message, to: Option<usize>) -> Result<(), TransportError> {
+        if let Some(to) = to.filter(|to| *to >= self.network.inboxes.len()) {
+            return Err(TransportError::NoSuchProcess(to));
+        }
+        // candidates are all sent before the first phase
+        let phase = message.phase().unwrap_or(Phase(0));
+        let network = &self.network;
+        let mut state = network.state.lock().expect("lock");
+        network.heal(&mut state, Some(phase));
+
+        let active = network
+            .schedule
+            .iter()
+            .enumerate()
+            .find(|(i, partition)| !state.healed[*i] && partition.covers(phase));
+        match (active, to) {
+            (None, None) => self.inner.send(message),
+            (None, Some(to)) => self.inner.send_to(to, message),
+            (Some((i, partition)), to) => {
+                state.started[i].get_or_insert_with(|| (network.clock)());
+                if let Some(group) = partition.group_of(self.id) {
+                    state.progress[i][group] = Some(phase);
+                }
+                let recipients = match to {
+                    Some(to) => to..to + 1,
+                    None => 0..network.inboxes.len(),
+                };
+                for to in recipients {
+                    if !partition.separates(self.id, to) {
+                        network.inboxes[to]
+                            .lock()
+                            .expect("lock")
+                            .push_back((self.id, message.clone()));
+                    } else if network.cross_traffic == CrossTraffic::Buffer {
+                        state.held.push((i, self.id, to, message.clone()));
+                    } else {
+                        debug!(to, ?message, "network partition dropped a message");
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Network {
+    // heals the partitions that a message of `phase` is past, and those that
+    // have been in effect for long enough
+    fn heal(&self, state: &mut State, phase: Option<Phase>) {
+        let now = (self.clock)();
+        for (i, partition) in self.schedule.iter().enumerate() {
+            let passed = phase.is_some_and(|phase| phase > partition.to);
+            let expired = state.started[i]
+                .is_some_and(|started| now.saturating_duration_since(started) >= self.heal_after);
+            if !state.healed[i] && (passed || expired) {
+                info!(%partition, "network partition healed");
+                state.healed[i] = true;
+                let (released, held): (Vec<_>, Vec<_>) = std::mem::take(&mut state.held)
+                    .into_iter()
+                    .partition(|(p, _, _, _)| *p == i);
+                state.held = held;
+                for (_, from, to, message) in released {
+                    self.inboxes[to]
+                        .lock()
+                        .expect("lock")
+                        .push_back((from, message));
+                }
+            }
+        }
+    }
+}
+
+impl Transport for PartitionedTransport {
+    fn num_senders(&self) -> usize {
+        self.inner.num_senders()
+    }
+
+    fn send(&self, message: Message) -> Result<(), TransportError> {
+        self.route(message, None)
+    }
+
+    fn send_to(&self, to: usize, message: Message) -> Result<(), TransportError> {
+        self.route(message, Some(to))
+    }
+
+    fn send_to_self(&self, message: Message) -> Result<(), TransportError> {
+        self.inner.send_to_self(message)
+    }
+
+    fn receive(&self) -> Result<(usize, Message), TransportError> {
+        loop {
+            if let Some(received) = self.try_receive()? {
+                return Ok(received);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    // the inbox goes first, since re-queued messages keep the inner transport
+    // busy while a process waits for the messages of its own phase
+    fn try_receive(&self) -> Result<Option<(usize, Message)>, TransportError> {
+        self.network
+            .heal(&mut self.network.state.lock().expect("lock"), None);
+        let inboxed = self.network.inboxes[self.id]
+            .lock()
+            .expect("lock")
+            .pop_front();
+        match inboxed {
+            Some(received) => Ok(Some(received)),
+            None => self.inner.try_receive(),
+        }
+    }
+
+    fn stopper(&self) -> Stopper {
+        self.inner.stopper()
+    }
+
+    fn num_malformed(&self) -> u64 {
+        self.inner.num_malformed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use crate::{
+        checker::SafetyChecker, message::Value, step::Behavior, transport::MessageChannel,
+    };
+
+    fn report(phase: u64) -> Message {
+        Message::Report {
+            phase: Phase(phase),
+            value: Value::One,
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let partition: Partition = "0..1,3|2@3..7".parse().expect("parse");
+        assert_eq!(
+            partition,
+            Partition {
+                groups: vec![vec![0, 1, 3], vec![2]],
+                from: Phase(3),
+                to: Phase(7),
+            }
+        );
+        assert_eq!(partition.to_string(), "0,1,3|2@3..7");
+        assert!("0..4|5..10".parse::<Partition>().is_err());
+        assert!("0..4|5..10@7..3".parse::<Partition>().is_err());
+    }
+
+    #[test]
+    fn buffers_until_healed() {
+        let (transports, partitions) = PartitionedTransport::wrap(
+            MessageChannel::new(3),
+            vec!["0,1|2@1..2".parse().expect("parse")],
+            CrossTraffic::Buffer,
+            Duration::from_secs(60),
+        );
+        transports[0].send(report(0)).expect("send");
+        for transport in &transports {
+            assert_eq!(transport.receive(), Ok((0, report(0))));
+        }
+        transports[0].send(report(1)).expect("send");
+        assert_eq!(transports[1].receive(), Ok((0, report(1))));
+        assert_eq!(transports[2].try_receive(), Ok(None));
+
+        transports[1].send(report(3)).expect("send");
+        assert_eq!(transports[2].receive(), Ok((0, report(1))));
+        assert_eq!(transports[2].receive(), Ok((1, report(3))));
+        assert_eq!(partitions.progress()[0].1, vec![Some(Phase(1)), None]);
+    }
+
+    #[test]
+    fn unicasts_respect_partitions() {
+        let (transports, _) = PartitionedTransport::wrap(
+            MessageChannel::new(3),
+            vec!["0,1|2@1..1".parse().expect("parse")],
+            CrossTraffic::Buffer,
+            Duration::from_secs(60),
+        );
+        transports[0].send_to(1, report(1)).expect("send");
+        transports[0].send_to(2, report(1)).expect("send");
+        assert_eq!(transports[1].receive(), Ok((0, report(1))));
+        assert_eq!(transports[2].try_receive(), Ok(None));
+        assert_eq!(transports[0].try_receive(), Ok(None));
+
+        transports[0].send_to(2, report(2)).expect("send");
+        assert_eq!(transports[2].receive(), Ok((0, report(1))));
+        assert_eq!(transports[2].receive(), Ok((0, report(2))));
+        assert_eq!(transports[1].try_receive(), Ok(None));
+    }
+
+    #[test]
+    fn drops_cross_traffic() {
+        let (transports, _) = PartitionedTransport::wrap(
+            MessageChannel::new(2),
+            vec!["0|1@0..0".parse().expect("parse")],
+            CrossTraffic::Drop,
+            Duration::from_secs(60),
+        );
+        transports[0].send(report(0)).expect("send");
+        assert_eq!(transports[0].receive(), Ok((0, report(0))));
+        transports[0].send(report(1)).expect("send");
+        assert_eq!(transports[1].receive(), Ok((0, report(1))));
+    }
+
+    #[test]
+    fn agreement_survives_healing() {
+        let (transports, partitions) = PartitionedTransport::wrap(
+            MessageChannel::new(5),
+            vec!["0,1|2..4@1..3".parse().expect("parse")],
+            CrossTraffic::Buffer,
+            Duration::from_secs(60),
+        );
+        let mut checker = SafetyChecker::for_simulation(5, 2, 2, &Behavior::Correct);
+        let mut decided = [false; 5];
+        for (id, outcome) in crate::simulate(2, 2, Behavior::Correct, transports, 7) {
+            assert_eq!(checker.observe(&id, &outcome), Ok(()));
+            decided[id.0] |= outcome.decided().is_some();
+            if decided.iter().all(|decided| *decided) {
+                break;
+            }
+        }
+        let (_, progress) = &partitions.progress()[0];
+        // the minority cannot get past the first phase on its own
+        assert_eq!(progress[0], Some(Phase(1)));
+        assert_eq!(progress[1], Some(Phase(3)));
+    }
+
+    // a clock that moves on by a millisecond whenever the network looks at it
+    fn ticking_clock() -> Clock {
+        let start = Instant::now();
+        let ticks = AtomicU64::new(0);
+        Box::new(move || start + Duration::from_millis(ticks.fetch_add(1, Ordering::SeqCst)))
+    }
+
+    #[test]
+    fn heals_a_partition_that_lasts_too_long() {
+        let now = Arc::new(Mutex::new(Instant::now()));
+        let clock = now.clone();
+        let (transports, partitions) = PartitionedTransport::wrap_with_clock(
+            MessageChannel::new(4),
+            vec!["0,1|2,3@1..3".parse().expect("parse")],
+            CrossTraffic::Buffer,
+            Duration::from_secs(1),
+            Box::new(move || *clock.lock().expect("lock")),
+        );
+        transports[0].send(report(1)).expect("send");
+        transports[2].send(report(1)).expect("send");
+        assert_eq!(transports[1].receive(), Ok((0, report(1))));
+        assert_eq!(transports[3].receive(), Ok((2, report(1))));
+
+        *now.lock().expect("lock") += Duration::from_millis(999);
+        assert_eq!(transports[1].try_receive(), Ok(None));
+        *now.lock().expect("lock") += Duration::from_millis(1);
+        assert_eq!(transports[1].try_receive(), Ok(Some((2, report(1)))));
+        assert_eq!(transports[3].try_receive(), Ok(Some((0, report(1)))));
+        assert_eq!(
+            partitions.progress()[0].1,
+            vec![Some(Phase(1)), Some(Phase(1))]
+        );
+    }
+
+    #[test]
+    fn heals_a_partition_no_side_gets_past_in_time() {
+        let (transports, partitions) = PartitionedTransport::wrap_with_clock(
+            MessageChannel::new(4),
+            vec!["0,1|2,3@1..3".parse().expect("parse")],
+            CrossTraffic::Buffer,
+            Duration::from_millis(50),
+            ticking_clock(),
+        );
+        let mut checker = SafetyChecker::for_simulation(4, 2, 1, &Behavior::Correct);
+        let mut decided = [false; 4];
+        for (id, outcome) in crate::simulate(2, 1, Behavior::Correct, transports, 7) {
+            assert_eq!(checker.observe(&id, &outcome), Ok(()));
+            decided[id.0] |= outcome.decided().is_some();
+            if decided.iter().all(|decided| *decided) {
+                break;
+            }
+        }
+        // neither half has the n - f processes to get past the first phase
+        let (_, progress) = &partitions.progress()[0];
+        assert_eq!(progress, &vec![Some(Phase(1)), Some(Phase(1))]);
+    }
+}
@@ -0,0 +1,564 @@
+use std::{
+    convert::{TryFrom, TryInto},
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use hmac_sha256::HMAC;
+use tracing::{error, warn};
+
+use crate::{
+    message::Message,
+    transport::{self, broadcast, Delivery, Stopper, Transport, TransportError},
+};
+
+// frames larger than this can only come from a confused or malicious peer
+pub(crate) const MAX_FRAME_LEN: u32 = 1 << 16;
+const RECONNECT_DELAY: Duration = Duration::from_millis(50);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+// how long either end of a new connection waits on the other to shake hands
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct TcpTransport {
+    id: usize,
+    local_addr: SocketAddr,
+    self_sender: Sender<Delivery<Message>>,
+    peers: Vec<Sender<Vec<u8>>>,
+    receiver: Receiver<Delivery<Message>>,
+    num_malformed: Arc<AtomicU64>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl TcpTransport {
+    /// Binds to `peers[id]` and connects lazily to every other address in
+    /// `peers`, the index in the list being the process id.
+    ///
+    /// A connection starts with the process that opened it proving its id
+    /// with `key`, which every process shares, and the other end only keeps
+    /// one connection per process at a time. The frames that follow aren't
+    /// authenticated, so the network must still keep others from tampering
+    /// with a connection.
+    pub fn new(id: usize, peers: Vec<SocketAddr>, key: &[u8]) -> io::Result<Self> {
+        let addr = peers.get(id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no address for this process")
+        })?;
+        let listener = TcpListener::bind(addr)?;
+        Self::from_listener(id, listener, peers, key)
+    }
+
+    /// Like `new`, but with an already bound listener, which is useful when
+    /// the port is picked by the OS.
+    pub fn from_listener(
+        id: usize,
+        listener: TcpListener,
+        peers: Vec<SocketAddr>,
+        key: &[u8],
+    ) -> io::Result<Self> {
+        if key.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "empty key"));
+        }
+        let key: Arc<[u8]> = key.into();
+        let local_addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let (self_sender, receiver) = std::sync::mpsc::channel();
+
+        let sender = self_sender.clone();
+        let accept_shutdown = shutdown.clone();
+        let num_malformed = Arc::new(AtomicU64::new(0));
+        let acceptor = Acceptor {
+            key: key.clone(),
+            connected: Arc::new(Mutex::new(vec![false; peers.len()])),
+            num_malformed: num_malformed.clone(),
+        };
+        thread::spawn(move || accept(listener, sender, acceptor, accept_shutdown));
+
+        let peers = peers
+            .into_iter()
+            .map(|addr| {
+                let (sender, receiver) = std::sync::mpsc::channel();
+                let key = key.clone();
+                let shutdown = shutdown.clone();
+                thread::spawn(move || write_to_peer(id, addr, &key, receiver, shutdown));
+                sender
+            })
+            .collect();
+
+        Ok(TcpTransport {
+            id,
+            local_addr,
+            self_sender,
+            peers,
+            receiver,
+            num_malformed,
+            shutdown,
+        })
+    }
+
+    /// Creates `num_processes` transports connected over loopback, sharing a
+    /// random key.
+    pub fn local(num_processes: usize) -> io::Result<Vec<Box<dyn Transport>>> {
+        let key: [u8; 32] = rand::random();
+        let mut listeners = vec![];
+        for _ in 0..num_processes {
+            listeners.push(TcpListener::bind("127.0.0.1:0")?);
+        }
+        let addrs = listeners
+            .iter()
+            .map(TcpListener::local_addr)
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut boxes = vec![];
+        for (i, listener) in listeners.into_iter().enumerate() {
+            let b: Box<dyn Transport> = Box::new(TcpTransport::from_listener(
+                i,
+                listener,
+                addrs.clone(),
+                &key,
+            )?);
+            boxes.push(b);
+        }
+        Ok(boxes)
+    }
+}
+
+impl Drop for TcpTransport {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // wake up the accept loop so that it notices the shutdown
+        let _ = TcpStream::connect(self.local_addr);
+    }
+}
+
+impl Transport for TcpTransport {
+    fn num_senders(&self) -> usize {
+        self.peers.len()
+    }
+
+    fn send(&self, message: Message) -> Result<(), TransportError> {
+        broadcast(self.id, self.peers.len(), |to| {
+            self.send_to(to, message.clone())
+        })
+    }
+
+    fn send_to(&self, to: usize, message: Message) -> Result<(), TransportError> {
+        if to == self.id {
+            self.send_to_self(message)
+        } else {
+            self.send_bytes(to, message.into())
+        }
+    }
+
+    fn send_to_self(&self, message: Message) -> Result<(), TransportError> {
+        self.self_sender
+            .send(Ok((self.id, message)))
+            .map_err(|_| TransportError::Disconnected)
+    }
+
+    // the writer of a peer only goes away once this transport shuts down
+    fn send_bytes(&self, to: usize, bytes: Vec<u8>) -> Result<(), TransportError> {
+        if to == self.id {
+            let message =
+                Message::try_from(bytes).map_err(|e| TransportError::Decode(e.to_string()))?;
+            self.send_to_self(message)
+        } else {
+            self.peers
+                .get(to)
+                .ok_or(TransportError::NoSuchProcess(to))?
+                .send(bytes)
+                .map_err(|_| TransportError::Disconnected)
+        }
+    }
+
+    fn receive(&self) -> Result<(usize, Message), TransportError> {
+        transport::recv(&self.receiver)
+    }
+
+    fn receive_timeout(&self, timeout: Duration) -> Result<(usize, Message), TransportError> {
+        transport::recv_timeout(&self.receiver, timeout)
+    }
+
+    fn try_receive(&self) -> Result<Option<(usize, Message)>, TransportError> {
+        transport::try_recv(&self.receiver)
+    }
+
+    fn stopper(&self) -> Stopper {
+        transport::stopper(&self.self_sender)
+    }
+
+    fn num_malformed(&self) -> u64 {
+        self.num_malformed.load(Ordering::SeqCst)
+    }
+}
+
+// what the readers of the connections that the accept loop takes share
+#[derive(Clone)]
+struct Acceptor {
+    key: Arc<[u8]>,
+    // whether each process has a connection open already
+    connected: Arc<Mutex<Vec<bool>>>,
+    num_malformed: Arc<AtomicU64>,
+}
+
+fn accept(
+    listener: TcpListener,
+    sender: Sender<Delivery<Message>>,
+    acceptor: Acceptor,
+    shutdown: Arc<AtomicBool>,
+) {
+    for stream in listener.incoming() {
+        if shutdown.load(Ordering::SeqCst) {
+            return;
+        }
+        match stream {
+            Ok(stream) => {
+                let sender = sender.clone();
+                let acceptor = acceptor.clone();
+                thread::spawn(move || read_from_peer(stream, sender, acceptor));
+            }
+            Err(e) => error!(error = %e, "failed to accept"),
+        }
+    }
+}
+
+fn read_from_peer(mut stream: TcpStream, sender: Sender<Delivery<Message>>, acceptor: Acceptor) {
+    let connection = match acceptor.welcome(&mut stream) {
+        Ok(Some(connection)) => connection,
+        // a dropped transport waking up its own accept loop ends up here too
+        _ => return,
+    };
+    let from = connection.from;
+    // the peer reconnects on its own, so a broken connection just ends here
+    while let Ok(frame) = read_frame(&mut stream) {
+        match Message::try_from(frame) {
+            Ok(message) => {
+                if sender.send(Ok((from, message))).is_err() {
+                    return;
+                }
+            }
+            Err(e) => {
+                warn!(from, error = e, "dropped a malformed frame");
+                acceptor.num_malformed.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+impl Acceptor {
+    // the accepting end of a handshake: challenges the other end to prove
+    // its id, and takes it unless the proof is wrong or that process has a
+    // connection already
+    fn welcome(&self, stream: &mut TcpStream) -> io::Result<Option<Connection>> {
+        let challenge: [u8; 32] = rand::random();
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        write_frame(stream, &challenge)?;
+        let hello = read_frame(stream)?;
+        let from = match parse_hello(&hello, &challenge, &self.key) {
+            Some(from) => from,
+            None => {
+                warn!("turned away a connection that failed to prove its id");
+                return Ok(None);
+            }
+        };
+        match self.connected.lock().expect("lock").get_mut(from) {
+            Some(connected) if !*connected => *connected = true,
+            Some(_) => {
+                warn!(from, "turned away a second connection from a process");
+                return Ok(None);
+            }
+            None => {
+                warn!(from, "turned away a connection from an unknown process");
+                return Ok(None);
+            }
+        }
+        let connection = Connection {
+            from,
+            connected: self.connected.clone(),
+        };
+        stream.set_read_timeout(None)?;
+        // an empty frame tells the other end that it's in
+        write_frame(stream, &[])?;
+        Ok(Some(connection))
+    }
+}
+
+// the open connection from a process, until it's dropped
+struct Connection {
+    from: usize,
+    connected: Arc<Mutex<Vec<bool>>>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.connected.lock().expect("lock")[self.from] = false;
+    }
+}
+
+fn write_to_peer(
+    id: usize,
+    addr: SocketAddr,
+    key: &[u8],
+    receiver: Receiver<Vec<u8>>,
+    shutdown: Arc<AtomicBool>,
+) {
+    let mut stream = None;
+    // the frames sent before a shutdown still go out over a live connection,
+    // since the peer may need them to finish its phase
+    for frame in receiver {
+        loop {
+            let mut connected = match stream.take().or_else(|| connect(id, addr, key, &shutdown)) {
+                Some(connected) => connected,
+                None => return,
+            };
+            match write_frame(&mut connected, &frame) {
+                Ok(()) => {
+                    stream = Some(connected);
+                    break;
+                }
+                Err(e) => warn!(%addr, error = %e, "failed to write"),
+            }
+        }
+    }
+}
+
+fn connect(id: usize, addr: SocketAddr, key: &[u8], shutdown: &AtomicBool) -> Option<TcpStream> {
+    let mut delay = RECONNECT_DELAY;
+    while !shutdown.load(Ordering::SeqCst) {
+        let connected = TcpStream::connect(addr).and_then(|mut stream| {
+            stream.set_nodelay(true)?;
+            introduce(&mut stream, id, key)?;
+            Ok(stream)
+        });
+        match connected {
+            Ok(stream) => return Some(stream),
+            Err(_) => {
+                thread::sleep(delay);
+                delay = std::cmp::min(delay * 2, MAX_RECONNECT_DELAY);
+            }
+        }
+    }
+    None
+}
+
+// the connecting end of a handshake: answers the challenge of the other end
+// with a hello, and fails unless it's let in
+fn introduce(stream: &mut TcpStream, id: usize, key: &[u8]) -> io::Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let challenge = read_frame(stream)?;
+    write_frame(stream, &hello(&challenge, id, key))?;
+    read_frame(stream)?;
+    stream.set_read_timeout(None)
+}
+
+// the id of the process that opened a connection, and a MAC over the
+// challenge and the id under the key that only the processes know
+fn hello(challenge: &[u8], id: usize, key: &[u8]) -> Vec<u8> {
+    let id = (id as u64).to_be_bytes();
+    let mut hello = id.to_vec();
+    hello.extend_from_slice(&mac(challenge, &id, key).finalize());
+    hello
+}
+
+fn parse_hello(frame: &[u8], challenge: &[u8], key: &[u8]) -> Option<usize> {
+    let id: [u8; 8] = frame.get(..8)?.try_into().ok()?;
+    let tag: [u8; 32] = frame.get(8..)?.try_into().ok()?;
+    if !mac(challenge, &id, key).finalize_verify(&tag) {
+        return None;
+    }
+    usize::try_from(u64::from_be_bytes(id)).ok()
+}
+
+fn mac(challenge: &[u8], id: &[u8], key: &[u8]) -> HMAC {
+    let mut mac = HMAC::new(key);
+    mac.update(challenge);
+    mac.update(id);
+    mac
+}
+
+fn write_frame(stream: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    let len: u32 = bytes
+        .len()
+        .try_into()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame too long"))?;
+    stream.write_all(&len.to_be_bytes())?;
+    stream.write_all(bytes)?;
+    stream.flush()
+}
+
+fn read_frame(stream: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len);
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too long"));
+    }
+    let mut bytes = vec![0; len as usize];
+    stream.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::message::{Phase, Value};
+
+    const KEY: &[u8] = b"key";
+
+    #[test]
+    fn framing() {
+        let mut buffer = vec![];
+        write_frame(&mut buffer, &[1, 2, 3]).expect("write");
+        write_frame(&mut buffer, &[]).expect("write");
+        assert_eq!(buffer, vec![0, 0, 0, 3, 1, 2, 3, 0, 0, 0, 0]);
+        let mut reader = &buffer[..];
+        assert_eq!(read_frame(&mut reader).expect("read"), vec![1, 2, 3]);
+        assert_eq!(read_frame(&mut reader).expect("read"), Vec::<u8>::new());
+        assert!(read_frame(&mut reader).is_err());
+
+        let mut reader = &[0xff, 0xff, 0xff, 0xff][..];
+        assert!(read_frame(&mut reader).is_err());
+    }
+
+    #[test]
+    fn broadcast_over_loopback() {
+        let transports = TcpTransport::local(3).expect("local");
+        let message = Message::Report {
+            phase: Phase(3),
+            value: Value::One,
+        };
+        transports[1].send(message.clone()).expect("send");
+        for transport in &transports {
+            assert_eq!(transport.num_senders(), 3);
+            assert_eq!(transport.receive(), Ok((1, message.clone())));
+        }
+    }
+
+    #[test]
+    fn unicast_over_loopback() {
+        let transports = TcpTransport::local(3).expect("local");
+        let message = Message::Report {
+            phase: Phase(2),
+            value: Value::Zero,
+        };
+        transports[0].send_to(2, message.clone()).expect("send");
+        transports[0].send_to(0, message.clone()).expect("send");
+        assert_eq!(transports[2].receive(), Ok((0, message.clone())));
+        assert_eq!(transports[0].receive(), Ok((0, message)));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(transports[1].try_receive(), Ok(None));
+    }
+
+    #[test]
+    fn needs_an_address_of_its_own() {
+        let addr = "127.0.0.1:0".parse().expect("addr");
+        let error = TcpTransport::new(1, vec![addr], KEY)
+            .err()
+            .expect("no address");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn ignores_connections_from_unknown_processes() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let transport =
+            TcpTransport::from_listener(0, listener, vec![addr], KEY).expect("transport");
+        let mut stream = TcpStream::connect(addr).expect("connect");
+        assert!(introduce(&mut stream, 7, KEY).is_err());
+        assert_eq!(transport.try_receive(), Ok(None));
+    }
+
+    #[test]
+    fn ignores_connections_without_the_key() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let transport =
+            TcpTransport::from_listener(0, listener, vec![addr], KEY).expect("transport");
+        let mut stream = TcpStream::connect(addr).expect("connect");
+        assert!(introduce(&mut stream, 0, b"guess").is_err());
+
+        // nor does a hello for another challenge
+        let mut stream = TcpStream::connect(addr).expect("connect");
+        read_frame(&mut stream).expect("challenge");
+        write_frame(&mut stream, &hello(&[0; 32], 0, KEY)).expect("write");
+        assert!(read_frame(&mut stream).is_err());
+        assert_eq!(transport.try_receive(), Ok(None));
+    }
+
+    #[test]
+    fn ignores_a_second_connection_from_a_process() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let transport =
+            TcpTransport::from_listener(0, listener, vec![addr, addr], KEY).expect("transport");
+        let mut first = TcpStream::connect(addr).expect("connect");
+        introduce(&mut first, 1, KEY).expect("introduce");
+        let mut second = TcpStream::connect(addr).expect("connect");
+        assert!(introduce(&mut second, 1, KEY).is_err());
+
+        // once the first one is gone, the process may connect again
+        drop(first);
+        let mut third = loop {
+            let mut stream = TcpStream::connect(addr).expect("connect");
+            if introduce(&mut stream, 1, KEY).is_ok() {
+                break stream;
+            }
+            thread::sleep(RECONNECT_DELAY);
+        };
+        let message = Message::Proposal {
+            phase: Phase(0),
+            value: None,
+        };
+        write_frame(&mut third, &Vec::<u8>::from(message.clone())).expect("write");
+        assert_eq!(transport.receive(), Ok((1, message)));
+    }
+
+    #[test]
+    fn reconnects_when_peer_restarts() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let sender_listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let sender_addr = sender_listener.local_addr().expect("addr");
+        let peers = vec![sender_addr, addr];
+        let sender =
+            TcpTransport::from_listener(0, sender_listener, peers.clone(), KEY).expect("sender");
+
+        let message = Message::Proposal {
+            phase: Phase(1),
+            value: None,
+        };
+        let receiver =
+            TcpTransport::from_listener(1, listener, peers.clone(), KEY).expect("receiver");
+        sender.send(message.clone()).expect("send");
+        assert_eq!(receiver.receive(), Ok((0, message)));
+        drop(receiver);
+
+        // bring the peer back on the same address, once the old listener is gone
+        let listener = loop {
+            if let Ok(listener) = TcpListener::bind(addr) {
+                break listener;
+            }
+            thread::sleep(RECONNECT_DELAY);
+        };
+        let receiver = TcpTransport::from_listener(1, listener, peers, KEY).expect("receiver");
+        let message = Message::Report {
+            phase: Phase(2),
+            value: Value::Zero,
+        };
+        // the first write after the drop may still go into the dead socket
+        loop {
+            sender.send(message.clone()).expect("send");
+            if receiver.receiver.recv_timeout(Duration::from_secs(1))
+                == Ok(Ok((0, message.clone())))
+            {
+                break;
+            }
+        }
+    }
+}
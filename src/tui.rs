@@ -0,0 +1,210 @@
+// the `tui` mode of the binary: a live table of the processes of a run
+
+use std::{
+    io::{self, Stdout},
+    sync::mpsc::{Receiver, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+use ben_or_randomized_consensus::{Id, Monitor, Outcome, ProcessState, Scenario, ScenarioRun};
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        event::{self, Event, KeyCode, KeyEventKind},
+        execute,
+        terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Layout},
+    style::{Color, Style},
+    widgets::{Block, Paragraph, Row, Table},
+    Frame, Terminal,
+};
+
+// how long to wait for a key before drawing again, which is also how long a
+// running simulation waits between outcomes
+const TICK: Duration = Duration::from_millis(50);
+
+// a discrete-event simulation runs on this thread, so that it can be paused
+// and stepped; the threaded transports can't be held back, so they report
+// their outcomes from a thread of their own
+enum Source {
+    Stepped(Box<ScenarioRun>),
+    Live(Receiver<(Id, Outcome)>),
+}
+
+struct View {
+    title: String,
+    paused: bool,
+    finished: bool,
+    last: Option<String>,
+}
+
+/// Runs the scenario, showing where every process is at until `q` is
+/// pressed.
+pub(crate) fn show(scenario: &Scenario) -> io::Result<()> {
+    let (run, trace) = scenario.run_traced()?;
+    let mut monitor = Monitor::new(&scenario.processes());
+    let mut view = View {
+        title: format!(
+            "Ben-Or: {} processes on {} with seed {}",
+            monitor.processes().len(),
+            scenario.transport,
+            scenario.seed.unwrap_or_default()
+        ),
+        paused: false,
+        finished: false,
+        last: None,
+    };
+    let mut source = match run {
+        ScenarioRun::Threads(run) => {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            thread::spawn(move || {
+                for outcome in run {
+                    if sender.send(outcome).is_err() {
+                        return;
+                    }
+                }
+            });
+            Source::Live(receiver)
+        }
+        run => Source::Stepped(Box::new(run)),
+    };
+
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    let result = Terminal::new(CrosstermBackend::new(io::stdout())).and_then(|mut terminal| loop {
+        let mut step = false;
+        if event::poll(TICK)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    match key.code {
+                        KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                        KeyCode::Char(' ') => view.paused = !view.paused,
+                        KeyCode::Char('s') => step = true,
+                        _ => {}
+                    }
+                }
+            }
+        }
+        let outcomes = match &mut source {
+            Source::Stepped(run) if !view.finished && (!view.paused || step) => {
+                let outcome = run.next();
+                view.finished = outcome.is_none();
+                outcome.into_iter().collect()
+            }
+            Source::Stepped(_) => vec![],
+            Source::Live(receiver) => {
+                let mut outcomes = vec![];
+                loop {
+                    match receiver.try_recv() {
+                        Ok(outcome) => outcomes.push(outcome),
+                        Err(TryRecvError::Empty) => break,
+                        Err(TryRecvError::Disconnected) => {
+                            view.finished = true;
+                            break;
+                        }
+                    }
+                }
+                outcomes
+            }
+        };
+        for event in trace.events() {
+            monitor.record(&event);
+        }
+        for (id, outcome) in outcomes {
+            monitor.observe(&id, &outcome);
+            view.last = Some(format!("Process {}: outcome: {}", id, outcome));
+        }
+        draw(&mut terminal, &view, &source, &monitor)?;
+    });
+    disable_raw_mode()?;
+    execute!(io::stdout(), LeaveAlternateScreen)?;
+    result
+}
+
+fn draw(
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    view: &View,
+    source: &Source,
+    monitor: &Monitor,
+) -> io::Result<()> {
+    let status = match (source, view.finished, view.paused) {
+        (_, true, _) => "finished; q: quit",
+        (Source::Stepped(_), false, true) => "paused; space: resume, s: step, q: quit",
+        (Source::Stepped(_), false, false) => "running; space: pause, q: quit",
+        (Source::Live(_), false, _) => "running; q: quit",
+    };
+    terminal.draw(|frame: &mut Frame| {
+        let [header, table, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        frame.render_widget(
+            Paragraph::new(format!("{} ({})", view.title, status)),
+            header,
+        );
+        let rows = monitor
+            .processes()
+            .iter()
+            .enumerate()
+            .map(|(id, state)| row(id, state));
+        let widths = [
+            Constraint::Length(7),
+            Constraint::Length(22),
+            Constraint::Length(5),
+            Constraint::Length(5),
+            Constraint::Length(9),
+            Constraint::Length(14),
+            Constraint::Min(10),
+        ];
+        frame.render_widget(
+            Table::new(rows, widths)
+                .header(
+                    Row::new([
+                        "Process", "Behavior", "Phase", "Value", "Waiting", "Decided", "Status",
+                    ])
+                    .style(Style::new().fg(Color::Cyan)),
+                )
+                .block(Block::bordered()),
+            table,
+        );
+        frame.render_widget(
+            Paragraph::new(view.last.clone().unwrap_or_default()),
+            footer,
+        );
+    })?;
+    Ok(())
+}
+
+fn row(id: usize, state: &ProcessState) -> Row<'static> {
+    let cell = |value: Option<String>| value.unwrap_or_else(|| String::from("-"));
+    let style = if state.halted.is_some() {
+        Style::new().fg(Color::Red)
+    } else if state.decided.is_some() {
+        Style::new().fg(Color::Green)
+    } else {
+        Style::new()
+    };
+    Row::new([
+        id.to_string(),
+        state.behavior.to_string(),
+        cell(state.phase.map(|phase| phase.to_string())),
+        cell(state.value.as_ref().map(ToString::to_string)),
+        cell(
+            state
+                .waiting
+                .map(|(kind, have, need)| format!("{} {}/{}", kind, have, need)),
+        ),
+        cell(
+            state
+                .decided
+                .as_ref()
+                .map(|(phase, value)| format!("{} in phase {}", value, phase)),
+        ),
+        state.halted.clone().unwrap_or_default(),
+    ])
+    .style(style)
+}